@@ -4,9 +4,25 @@ use core::mem;
 
 // Declaration similar to network_types
 pub const BIER_ETHERTYPE: u16 = 0xAB37_u16.to_be();
+pub const BIER_MPLS_ETHERTYPE: u16 = 0x8847_u16.to_be();
 
+// First nibble of the header if the BIFT-id is an MPLS label
+pub const MPLS_BIER_NIBBLE: u8 = 0b0101;
+
+// Size of the largest header (4096 bit BitString), length on the wire depends on the BSL
 pub const BIER_HEADER_SIZE: usize = size_of::<BierHdr>();
 
+// Size of the header without BitString
+pub const BIER_FIXED_HEADER_SIZE: usize = 12;
+
+// Offsets of the header words
+pub const WORD0_OFFSET: usize = 0;
+pub const WORD1_OFFSET: usize = 4;
+pub const WORD2_OFFSET: usize = 8;
+
+// Maximum length of the BitString in bytes
+pub const MAX_BITSTRING_LEN: usize = 512;
+
 // Header according to: https://datatracker.ietf.org/doc/html/rfc8296
 //      0                   1                   2                   3
 //      0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
@@ -33,7 +49,26 @@ pub const BIER_HEADER_SIZE: usize = size_of::<BierHdr>();
 //       6: 2048 bits
 //       7: 4096 bits
 
+/// Length of the BitString in bytes for a BSL value, 0 if the BSL is not defined
+pub const fn bitstring_len(bsl: u8) -> usize {
+    match bsl {
+        1..=7 => 8 << (bsl - 1),
+        _ => 0,
+    }
+}
+
+/// Length of the BIER header in bytes for a BSL value, 0 if the BSL is not defined
+pub const fn header_len(bsl: u8) -> usize {
+    match bitstring_len(bsl) {
+        0 => 0,
+        len => BIER_FIXED_HEADER_SIZE + len,
+    }
+}
+
 // Proto field is based on: https://www.iana.org/assignments/bier/bier.xhtml
+pub const BIER_PROTO_ETHERNET: u8 = 3;
+pub const BIER_PROTO_IPV4: u8 = 4;
+pub const BIER_PROTO_IPV6: u8 = 6;
 
 #[bitfield]
 #[repr(u32)]
@@ -73,7 +108,7 @@ pub struct BierHdr {
     pub word0: Word0,
     pub word1: Word1,
     pub word2: Word2,
-    pub bitstring: [u8; 32], // 256 bits (four 32-bit words)
+    pub bitstring: [u8; MAX_BITSTRING_LEN], // Up to 4096 bits, only the first bitstring_len(bsl) bytes are used
 }
 
 fn reverse_bits_in_bytes(buf: &mut [u8]) {
@@ -96,12 +131,12 @@ impl BierHdr {
             word0: Word0::new(),
             word1: Word1::new(),
             word2: Word2::new(),
-            bitstring: [0; 32],
+            bitstring: [0; MAX_BITSTRING_LEN],
         }
     }
 
-     pub fn into_bytes(self) -> [u8; 44] {
-        let mut buf = [0u8; 44];
+     pub fn into_bytes(self) -> [u8; BIER_HEADER_SIZE] {
+        let mut buf = [0u8; BIER_HEADER_SIZE];
 
         // Serialize each 32-bit word
         // Problem: The bits are misordered!
@@ -110,8 +145,8 @@ impl BierHdr {
         buf[4..8].copy_from_slice(&self.word1.into_bytes());
         buf[8..12].copy_from_slice(&self.word2.into_bytes());
 
-        // Serialize the 256-bit bitstring
-        buf[12..44].copy_from_slice(&self.bitstring);
+        // Serialize the bitstring, header_len() bytes of the buffer are valid
+        buf[BIER_FIXED_HEADER_SIZE..].copy_from_slice(&self.bitstring);
 
         buf
     }
@@ -254,19 +289,36 @@ impl BierHdr {
 
     // BitString getters and setters
     
-    /// Get the BitString as a byte array
-    pub fn bitstring(&self) -> [u8; 32] {
-        self.bitstring
+    /// Get the length of the BitString in bytes according to the BSL
+    pub fn bitstring_len(&self) -> usize {
+        bitstring_len(self.bsl())
+    }
+
+    /// Get the length of the header in bytes according to the BSL
+    pub fn header_len(&self) -> usize {
+        header_len(self.bsl())
+    }
+
+    /// Get the BitString as a byte slice, its length is given by the BSL
+    pub fn bitstring(&self) -> &[u8] {
+        &self.bitstring[..self.bitstring_len()]
     }
     
-    /// Set the BitString from a byte array
-    pub fn set_bitstring(&mut self, value: [u8; 32]) {
-        self.bitstring = value;
+    /// Set the BitString from a byte slice, fails if the length does not match the BSL
+    /// or the BSL is not defined
+    pub fn set_bitstring(&mut self, value: &[u8]) -> bool {
+        let len = self.bitstring_len();
+        if len == 0 || value.len() != len {
+            return false;
+        }
+        self.bitstring = [0; MAX_BITSTRING_LEN];
+        self.bitstring[..len].copy_from_slice(value);
+        true
     }
     
     /// Get a specific bit from the BitString
     pub fn get_bit(&self, bit_index: usize) -> bool {
-        if bit_index >= 256 {
+        if bit_index >= self.bitstring_len() * 8 {
             return false;
         }
         let byte_index = bit_index / 8;
@@ -276,7 +328,7 @@ impl BierHdr {
     
     /// Set a specific bit in the BitString
     pub fn set_bit(&mut self, bit_index: usize, value: bool) {
-        if bit_index >= 256 {
+        if bit_index >= self.bitstring_len() * 8 {
             return;
         }
         let byte_index = bit_index / 8;
@@ -380,11 +432,12 @@ mod tests {
     #[test]
     fn test_bitstring_operations() {
         let mut hdr = BierHdr::new();
+        hdr.set_bsl(3);
         
         // Test setting and getting the entire bitstring
         let test_bitstring = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
-        hdr.set_bitstring(test_bitstring);
-        assert_eq!(hdr.bitstring(), test_bitstring);
+        assert!(hdr.set_bitstring(&test_bitstring));
+        assert_eq!(hdr.bitstring(), &test_bitstring);
         
         // Test individual bit operations
         hdr.set_bit(0, true);
@@ -396,4 +449,47 @@ mod tests {
         hdr.set_bit(63, true);
         assert_eq!(hdr.get_bit(63), true);
     }
+
+    #[test]
+    fn test_bsl_lengths() {
+        assert_eq!(bitstring_len(0), 0);
+        assert_eq!(bitstring_len(1), 8);
+        assert_eq!(bitstring_len(3), 32);
+        assert_eq!(bitstring_len(4), 64);
+        assert_eq!(bitstring_len(7), MAX_BITSTRING_LEN);
+        assert_eq!(bitstring_len(8), 0);
+
+        assert_eq!(header_len(3), 44);
+        assert_eq!(header_len(7), BIER_HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_variable_bitstring() {
+        let mut hdr = BierHdr::new();
+        hdr.set_bsl(1);
+
+        // Length has to match the BSL
+        assert!(!hdr.set_bitstring(&[0xff; 32]));
+        assert!(hdr.set_bitstring(&[0xff; 8]));
+        assert_eq!(hdr.bitstring().len(), 8);
+
+        // Bits beyond the BSL are ignored
+        hdr.set_bit(64, true);
+        assert!(!hdr.get_bit(64));
+        hdr.set_bsl(4);
+        assert!(!hdr.get_bit(64));
+
+        let bytes = hdr.into_bytes();
+        assert_eq!(&bytes[BIER_FIXED_HEADER_SIZE..BIER_FIXED_HEADER_SIZE + 8], &[0xff; 8]);
+    }
+
+    #[test]
+    fn test_undefined_bsl() {
+        // An empty BitString does not make an undefined BSL valid
+        let mut hdr = BierHdr::new();
+        for bsl in [0, 8, 15] {
+            hdr.set_bsl(bsl);
+            assert!(!hdr.set_bitstring(&[]));
+        }
+    }
 }
@@ -0,0 +1,68 @@
+// BIER header carried in an IPv6 Destination Options header, based on:
+// https://datatracker.ietf.org/doc/html/draft-ietf-bier-bierin6
+// https://datatracker.ietf.org/doc/html/draft-xie-bier-ipv6-encapsulation
+//
+//     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//     |                  IPv6 header (next header 60)                 ~
+//     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//     |  Next Header  |  Hdr Ext Len  |  Option Type  | Opt Data Len  |
+//     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//     |                  BIER header (without padding)                ~
+//     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+
+use crate::bier::{header_len, BIER_PROTO_ETHERNET, BIER_PROTO_IPV4, BIER_PROTO_IPV6};
+
+pub const IPV6_HDR_LEN: usize = 40;
+pub const IPV6_NEXT_HDR_OFFSET: usize = 6;
+pub const IPV6_SRC_OFFSET: usize = 8;
+pub const IPV6_DST_OFFSET: usize = 24;
+pub const IPV6_HOP_LIMIT: u8 = 64;
+
+pub const NEXT_HDR_DEST_OPTS: u8 = 60;
+pub const NEXT_HDR_NONE: u8 = 59;
+
+// Destination Options header up to the option data
+pub const DEST_OPTS_HDR_LEN: usize = 4;
+pub const DEST_OPTS_TYPE_OFFSET: usize = 2;
+
+// No option type is assigned yet, experimental value (RFC 4727) that is skipped
+// by other nodes and may change en route
+pub const BIERIN6_OPTION_TYPE: u8 = 0x3E;
+
+// Offset of the BIER header behind the Ethernet header
+pub const BIERIN6_BIER_OFFSET: usize = IPV6_HDR_LEN + DEST_OPTS_HDR_LEN;
+
+// BIER header has to fit into the 8 bit option data length, up to 1024 bits
+pub const BIERIN6_MAX_BSL: u8 = 5;
+
+/// Header extension length of the Destination Options header in 8 byte units, without the first 8 bytes.
+/// BIER headers are a multiple of 8 bytes plus 4, so no padding is needed.
+pub const fn dest_opts_ext_len(bsl: u8) -> u8 {
+    ((DEST_OPTS_HDR_LEN + header_len(bsl)) / 8 - 1) as u8
+}
+
+/// IPv6 next header of the payload for a BIER next protocol
+pub const fn next_header(proto: u8) -> u8 {
+    match proto {
+        BIER_PROTO_IPV4 => 4,
+        BIER_PROTO_IPV6 => 41,
+        BIER_PROTO_ETHERNET => 143,
+        _ => NEXT_HDR_NONE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dest_opts_len() {
+        for bsl in 1..=BIERIN6_MAX_BSL {
+            let len = DEST_OPTS_HDR_LEN + header_len(bsl);
+            assert_eq!(len % 8, 0);
+            assert_eq!((dest_opts_ext_len(bsl) as usize + 1) * 8, len);
+            assert!(header_len(bsl) <= u8::MAX as usize);
+        }
+        assert!(header_len(BIERIN6_MAX_BSL + 1) > u8::MAX as usize);
+    }
+}
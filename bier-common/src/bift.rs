@@ -0,0 +1,191 @@
+// Bit Index Forwarding Table according to: https://datatracker.ietf.org/doc/html/rfc8279#section-6.4
+//
+// Every BFR-id that is reachable over a neighbor maps to the neighbor and its
+// Forwarding Bit Mask (F-BM), i.e. all BFR-ids reachable over the same neighbor.
+// BFR-ids are bit positions counted from 1, starting at the rightmost bit of the BitString.
+// The F-BM is aligned to the right end, so it applies to BitStrings of all lengths.
+
+use crate::bier::MAX_BITSTRING_LEN;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BiftKey {
+    pub bift_id: u32,
+    pub bfr_id: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BiftNeighbor {
+    pub ifindex: u32,
+    pub src_mac: [u8; 6],
+    pub nh_mac: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BiftEntry {
+    pub nbr: BiftNeighbor,
+    pub fbm: [u8; MAX_BITSTRING_LEN],
+}
+
+// Forwarding mode of a BIFT-id
+pub const BIFT_MODE_BIER: u8 = 0;
+pub const BIFT_MODE_TE: u8 = 1;
+
+// Handling of packets without a matching bit on ingress
+pub const NO_MATCH_DROP: u8 = 0;
+// Raw BIER frame is passed to the stack, e.g. for tcpdump or a userspace BFR
+pub const NO_MATCH_PASS: u8 = 1;
+pub const NO_MATCH_REDIRECT: u8 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BiftInfo {
+    pub mode: u8,
+    // BIFT-id is accepted as MPLS label on ingress
+    pub mpls: u8,
+    pub no_match: u8,
+    pub _pad: u8,
+    // Target interface of NO_MATCH_REDIRECT
+    pub redirect_ifindex: u32,
+    // BIER-TE only: all bits of adjacencies of this BFR, cleared in every forwarded copy
+    pub adjacent_bits: [u8; MAX_BITSTRING_LEN],
+}
+
+// BIER-TE adjacency types according to: https://datatracker.ietf.org/doc/html/rfc9262#section-3.2
+pub const ADJ_FORWARD_CONNECTED: u8 = 1;
+pub const ADJ_FORWARD_ROUTED: u8 = 2;
+pub const ADJ_LOCAL_DECAP: u8 = 3;
+pub const ADJ_ECMP: u8 = 4;
+
+// Maximum number of members of an ECMP adjacency
+pub const MAX_ECMP: usize = 4;
+
+// BIER-TE adjacency of a bit position, stored with the same key as the BIFT
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TeAdjacency {
+    pub kind: u8,
+    pub num_nbrs: u8,
+    pub _pad: [u8; 2],
+    pub nbrs: [BiftNeighbor; MAX_ECMP],
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BiftKey {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BiftEntry {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BiftInfo {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TeAdjacency {}
+
+/// Byte index and mask of a BFR-id in a BitString of `len` bytes
+#[inline(always)]
+pub fn bfr_id_to_bit(bfr_id: u32, len: usize) -> Option<(usize, u8)> {
+    if bfr_id == 0 || bfr_id as usize > len * 8 {
+        return None;
+    }
+    let pos = (bfr_id - 1) as usize;
+    Some((len - 1 - pos / 8, 1 << (pos % 8)))
+}
+
+/// BFR-id of a bit given by byte index and bit offset (0 = most significant) in a BitString of `len` bytes
+#[inline(always)]
+pub fn bit_to_bfr_id(byte_index: usize, bit_offset: usize, len: usize) -> u32 {
+    ((len - 1 - byte_index) * 8 + (7 - bit_offset) + 1) as u32
+}
+
+/// Set the bit of a BFR-id in a BitString
+pub fn set_bfr_id(bitstring: &mut [u8], bfr_id: u32) -> bool {
+    match bfr_id_to_bit(bfr_id, bitstring.len()) {
+        Some((index, mask)) => {
+            bitstring[index] |= mask;
+            true
+        }
+        None => false,
+    }
+}
+
+impl BiftEntry {
+    /// Create a new BiftEntry with an empty F-BM
+    pub fn new(nbr: BiftNeighbor) -> Self {
+        Self {
+            nbr,
+            fbm: [0; MAX_BITSTRING_LEN],
+        }
+    }
+}
+
+impl BiftInfo {
+    /// Create a new BiftInfo without adjacent bits, packets without matching bit are dropped
+    pub fn new(mode: u8, mpls: bool) -> Self {
+        Self {
+            mode,
+            mpls: mpls as u8,
+            no_match: NO_MATCH_DROP,
+            _pad: 0,
+            redirect_ifindex: 0,
+            adjacent_bits: [0; MAX_BITSTRING_LEN],
+        }
+    }
+}
+
+impl TeAdjacency {
+    /// Create a new TeAdjacency, at most MAX_ECMP neighbors are used
+    pub fn new(kind: u8, nbrs: &[BiftNeighbor]) -> Self {
+        let mut adj = Self {
+            kind,
+            num_nbrs: 0,
+            _pad: [0; 2],
+            nbrs: [BiftNeighbor {
+                ifindex: 0,
+                src_mac: [0; 6],
+                nh_mac: [0; 6],
+            }; MAX_ECMP],
+        };
+        for (slot, nbr) in adj.nbrs.iter_mut().zip(nbrs.iter()) {
+            *slot = *nbr;
+            adj.num_nbrs += 1;
+        }
+        adj
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bfr_id_bit_mapping() {
+        // BFR-id 1 is the rightmost bit
+        assert_eq!(bfr_id_to_bit(1, 32), Some((31, 0x01)));
+        assert_eq!(bfr_id_to_bit(8, 32), Some((31, 0x80)));
+        assert_eq!(bfr_id_to_bit(9, 32), Some((30, 0x01)));
+        assert_eq!(bfr_id_to_bit(256, 32), Some((0, 0x80)));
+
+        // Out of range
+        assert_eq!(bfr_id_to_bit(0, 32), None);
+        assert_eq!(bfr_id_to_bit(257, 32), None);
+
+        for bfr_id in 1..=256 {
+            let (index, mask) = bfr_id_to_bit(bfr_id, 32).unwrap();
+            let offset = mask.leading_zeros() as usize;
+            assert_eq!(bit_to_bfr_id(index, offset, 32), bfr_id);
+        }
+    }
+
+    #[test]
+    fn test_set_bfr_id() {
+        let mut bs = [0u8; 32];
+        assert!(set_bfr_id(&mut bs, 1));
+        assert!(set_bfr_id(&mut bs, 10));
+        assert!(!set_bfr_id(&mut bs, 300));
+        assert_eq!(bs[31], 0x01);
+        assert_eq!(bs[30], 0x02);
+    }
+}
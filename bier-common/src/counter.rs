@@ -0,0 +1,44 @@
+// Packet and byte counter, kept per CPU in the data plane and summed in userspace
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Counter {}
+
+impl Counter {
+    /// Account a single packet with the given length
+    pub fn add_packet(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+
+    /// Sum of the values of all CPUs
+    pub fn sum<'a>(counters: impl IntoIterator<Item = &'a Counter>) -> Counter {
+        counters.into_iter().fold(Counter::default(), |acc, counter| Counter {
+            packets: acc.packets + counter.packets,
+            bytes: acc.bytes + counter.bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_sum() {
+        let mut cpu0 = Counter::default();
+        cpu0.add_packet(100);
+        cpu0.add_packet(50);
+        let mut cpu1 = Counter::default();
+        cpu1.add_packet(1500);
+
+        let total = Counter::sum(&[cpu0, cpu1, Counter::default()]);
+        assert_eq!(total, Counter { packets: 3, bytes: 1650 });
+    }
+}
@@ -0,0 +1,86 @@
+// Per mapping configuration of the BIER encapsulation on egress
+
+use crate::bier::BIER_HEADER_SIZE;
+
+// Encapsulation according to: https://datatracker.ietf.org/doc/html/rfc8296#section-2
+pub const ENCAP_ETHERNET: u8 = 0;
+pub const ENCAP_MPLS: u8 = 1;
+// BIERin6, outer IPv6 header with the BIER header as destination option
+pub const ENCAP_IPV6: u8 = 2;
+
+// Handling of packets that exceed the interface MTU once encapsulated
+pub const MTU_POLICY_DROP: u8 = 0;
+pub const MTU_POLICY_PASS: u8 = 1;
+// Packet too big is sent back to the local sender with the MTU left for the payload
+pub const MTU_POLICY_ICMP: u8 = 2;
+
+// Source of the entropy field, static uses the value of the configured header
+pub const ENTROPY_STATIC: u8 = 0;
+// Hash of the inner flow, so all packets of a flow take the same path
+pub const ENTROPY_FLOW_HASH: u8 = 1;
+pub const ENTROPY_RANDOM: u8 = 2;
+
+pub const ENTROPY_MASK: u32 = 0xF_FFFF;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct EgressEntry {
+    pub hdr: [u8; BIER_HEADER_SIZE],
+    // Outer IPv6 addresses, only used for ENCAP_IPV6
+    pub src: [u8; 16],
+    pub dst: [u8; 16],
+    pub encap: u8,
+    pub mtu_policy: u8,
+    pub entropy_mode: u8,
+    pub _pad: u8,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for EgressEntry {}
+
+impl EgressEntry {
+    /// Create a new EgressEntry from a serialized BIER header
+    pub fn new(hdr: [u8; BIER_HEADER_SIZE], encap: u8, src: [u8; 16], dst: [u8; 16]) -> Self {
+        Self {
+            hdr,
+            src,
+            dst,
+            encap,
+            mtu_policy: MTU_POLICY_DROP,
+            entropy_mode: ENTROPY_STATIC,
+            _pad: 0,
+        }
+    }
+}
+
+/// Hash of the words of a flow tuple, not cryptographically secure
+#[inline(always)]
+pub fn flow_hash<const N: usize>(words: &[u32; N]) -> u32 {
+    let mut hash: u32 = 0x9E37_79B9;
+    let mut i = 0;
+    while i < N {
+        hash = (hash ^ words[i]).wrapping_mul(0x85EB_CA6B);
+        hash ^= hash >> 15;
+        i += 1;
+    }
+    hash
+}
+
+/// Fold a 32 bit hash into the 20 bit entropy field
+pub const fn fold_entropy(hash: u32) -> u32 {
+    (hash ^ (hash >> 20)) & ENTROPY_MASK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_entropy() {
+        let a = fold_entropy(flow_hash(&[0x0a000001, 0xef010101, (1234 << 16) | 5000, 17]));
+        let b = fold_entropy(flow_hash(&[0x0a000001, 0xef010101, (1235 << 16) | 5000, 17]));
+        assert!(a <= ENTROPY_MASK && b <= ENTROPY_MASK);
+        assert_ne!(a, b);
+        assert_eq!(a, fold_entropy(flow_hash(&[0x0a000001, 0xef010101, (1234 << 16) | 5000, 17])));
+    }
+}
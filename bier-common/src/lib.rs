@@ -1,3 +1,9 @@
 #![no_std]
 
-pub mod bier;
\ No newline at end of file
+pub mod bier;
+pub mod bierin6;
+pub mod bift;
+pub mod counter;
+pub mod egress;
+pub mod reason;
+pub mod ttl;
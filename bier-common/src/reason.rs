@@ -0,0 +1,82 @@
+// Reasons why BIER packets or packets of mapped groups are dropped or passed unmodified,
+// counted per CPU by the eBPF programs. Other traffic is passed on without being counted.
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    // Dropped
+    NoBitMatch = 0,
+    InvalidBsl = 1,
+    UnknownProto = 2,
+    TtlExpired = 3,
+    OutOfBounds = 4,
+    AdjustHeadFailed = 5,
+    LoadStoreFailed = 6,
+    MapLookupFailed = 7,
+    MtuExceeded = 8,
+    CloneRedirectFailed = 9,
+    // Passed unmodified
+    ChangeHeadFailed = 10,
+    MtuPassed = 11,
+    NoBitMatchPassed = 12,
+    NoBitMatchRedirected = 13,
+}
+
+pub const NUM_REASONS: u32 = 14;
+
+impl Reason {
+    pub const ALL: [Reason; NUM_REASONS as usize] = [
+        Reason::NoBitMatch,
+        Reason::InvalidBsl,
+        Reason::UnknownProto,
+        Reason::TtlExpired,
+        Reason::OutOfBounds,
+        Reason::AdjustHeadFailed,
+        Reason::LoadStoreFailed,
+        Reason::MapLookupFailed,
+        Reason::MtuExceeded,
+        Reason::CloneRedirectFailed,
+        Reason::ChangeHeadFailed,
+        Reason::MtuPassed,
+        Reason::NoBitMatchPassed,
+        Reason::NoBitMatchRedirected,
+    ];
+
+    /// Packets with this reason are dropped, all others are passed unmodified
+    pub const fn is_drop(&self) -> bool {
+        (*self as u32) < Reason::ChangeHeadFailed as u32
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Reason::NoBitMatch => "no BFER bit match",
+            Reason::InvalidBsl => "invalid BSL",
+            Reason::UnknownProto => "unknown next protocol",
+            Reason::TtlExpired => "TTL expired",
+            Reason::OutOfBounds => "out of bounds",
+            Reason::AdjustHeadFailed => "adjust head failed",
+            Reason::LoadStoreFailed => "load/store failed",
+            Reason::MapLookupFailed => "map lookup failed",
+            Reason::MtuExceeded => "MTU exceeded",
+            Reason::CloneRedirectFailed => "clone redirect failed",
+            Reason::ChangeHeadFailed => "change head failed",
+            Reason::MtuPassed => "MTU exceeded, not encapsulated",
+            Reason::NoBitMatchPassed => "no BFER bit match, passed",
+            Reason::NoBitMatchRedirected => "no BFER bit match, redirected",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason_index() {
+        // Reasons are used as index of the counter array
+        for (i, reason) in Reason::ALL.iter().enumerate() {
+            assert_eq!(*reason as usize, i);
+        }
+        assert!((Reason::NoBitMatchRedirected as u32) < NUM_REASONS);
+    }
+}
@@ -0,0 +1,20 @@
+// TTL handling according to: https://datatracker.ietf.org/doc/html/rfc8296#section-2.1.1.2
+
+// Notification sent to userspace if the TTL of a BIER packet expires
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TtlExpiry {
+    pub bift_id: u32,
+    pub bfir_id: u16,
+    pub _pad: [u8; 2],
+}
+
+impl TtlExpiry {
+    pub fn new(bift_id: u32, bfir_id: u16) -> Self {
+        Self {
+            bift_id,
+            bfir_id,
+            _pad: [0; 2],
+        }
+    }
+}
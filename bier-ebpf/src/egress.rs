@@ -1,104 +1,448 @@
-use core::mem::offset_of;
+use core::{
+    mem::{self, offset_of},
+    ptr::{addr_of, addr_of_mut},
+};
 
 use aya_ebpf::{
-    bindings::{bpf_cmd::BPF_MAP_UPDATE_ELEM, TC_ACT_OK},
-    helpers::r#gen::{bpf_map_lookup_elem, bpf_skb_change_head, bpf_skb_store_bytes},
+    bindings::{
+        bpf_check_mtu_ret::BPF_MTU_CHK_RET_SUCCESS, bpf_cmd::BPF_MAP_UPDATE_ELEM,
+        bpf_fib_lookup as FibParams, BPF_FIB_LKUP_RET_SUCCESS, BPF_FIB_LOOKUP_OUTPUT, TC_ACT_OK,
+    },
+    cty::{c_int, c_long},
+    helpers::r#gen::{
+        bpf_check_mtu, bpf_fib_lookup, bpf_get_hash_recalc, bpf_get_prandom_u32,
+        bpf_map_lookup_elem, bpf_skb_change_head, bpf_skb_store_bytes,
+    },
     macros::map,
-    maps::{HashMap, PerCpuArray},
+    maps::{HashMap, PerCpuArray, PerCpuHashMap},
     memcpy,
     programs::TcContext,
 };
 use aya_log_ebpf::{info, warn};
 use network_types::{
     eth::{self, EtherType},
-    ip::Ipv4Hdr,
+    ip::{Ipv4Hdr, Ipv6Hdr},
+};
+
+use bier_common::{
+    bier::{
+        self, header_len, BierHdr, Word1, Word2, BIER_ETHERTYPE, BIER_HEADER_SIZE,
+        BIER_MPLS_ETHERTYPE, WORD1_OFFSET, WORD2_OFFSET,
+    },
+    bierin6::{
+        self, dest_opts_ext_len, BIERIN6_BIER_OFFSET, BIERIN6_MAX_BSL, BIERIN6_OPTION_TYPE,
+        DEST_OPTS_HDR_LEN, IPV6_DST_OFFSET, IPV6_HDR_LEN, IPV6_HOP_LIMIT, IPV6_NEXT_HDR_OFFSET,
+        IPV6_SRC_OFFSET, NEXT_HDR_DEST_OPTS,
+    },
+    counter::Counter,
+    egress::{
+        flow_hash, fold_entropy, EgressEntry, ENCAP_IPV6, ENCAP_MPLS, ENTROPY_FLOW_HASH,
+        ENTROPY_RANDOM, MTU_POLICY_ICMP, MTU_POLICY_PASS,
+    },
+    reason::Reason,
 };
 
-use bier_common::bier::{self, BierHdr, BIER_ETHERTYPE, BIER_HEADER_SIZE};
+// Address family for FIB lookups
+const AF_INET6: u8 = 10;
 
-use crate::{util::{ptr_at, tc_ptr_at, tc_ptr_mut_at, EthHdr}, NUM_CPUS};
+// Transport protocols with ports in the flow hash
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+use crate::{
+    icmp::{packet_too_big_v4, packet_too_big_v6},
+    reason::count_reason,
+    util::{ptr_at, tc_ptr_at, tc_ptr_mut_at, EthHdr},
+    NUM_CPUS,
+};
 
 #[map]
-static IPMC4_TO_BIER: HashMap<u32, [u8; BIER_HEADER_SIZE]> =
-    HashMap::<u32, [u8; BIER_HEADER_SIZE]>::with_max_entries(1024, 0);
+static IPMC4_TO_BIER: HashMap<u32, EgressEntry> =
+    HashMap::<u32, EgressEntry>::with_max_entries(1024, 0);
 #[map]
-static IPMC6_TO_BIER: HashMap<u128, [u8; BIER_HEADER_SIZE]> =
-    HashMap::<u128, [u8; BIER_HEADER_SIZE]>::with_max_entries(1024, 0);
+static IPMC6_TO_BIER: HashMap<u128, EgressEntry> =
+    HashMap::<u128, EgressEntry>::with_max_entries(1024, 0);
 
+// Encapsulated packets and bytes per mapping, per CPU to avoid lost updates
 #[map]
-static mut v4_EGRESS_COUNTER: HashMap<u32, u64> = HashMap::<u32, u64>::with_max_entries(1024, 0);
+static v4_EGRESS_COUNTER: PerCpuHashMap<u32, Counter> =
+    PerCpuHashMap::<u32, Counter>::with_max_entries(1024, 0);
 #[map]
-static mut v6_EGRESS_COUNTER: HashMap<u128, u64> = HashMap::<u128, u64>::with_max_entries(1024, 0);
+static v6_EGRESS_COUNTER: PerCpuHashMap<u128, Counter> =
+    PerCpuHashMap::<u128, Counter>::with_max_entries(1024, 0);
 
 #[inline(always)]
-pub fn process_egress(ctx: TcContext) -> Result<i32, ()> {
+pub fn process_egress(ctx: TcContext) -> Result<i32, Reason> {
     // Get memory offset to ethertype field of ethhdr
     let ethertype_offset = offset_of!(EthHdr, ether_type);
 
     // Get ethertype over memory offset, error leads to go to next action and skip processing
-    let ethertype = u16::from_be(ctx.load(ethertype_offset).map_err(|_| ())?);
-
-    // TODO: add IPv6 handling
-    if ethertype != 0x0800 {
-        info!(&ctx, "Passed non IP packet: {}", ethertype);
+    let Ok(ethertype) = ctx.load::<u16>(ethertype_offset) else {
         return Ok(TC_ACT_OK);
+    };
+
+    // Traffic other than mapped IP multicast is passed on without being counted
+    match u16::from_be(ethertype) {
+        0x0800 => process_ipv4(&ctx),
+        0x86DD => process_ipv6(&ctx),
+        _ => Ok(TC_ACT_OK),
     }
+}
 
-    let ipv4hdr: *const Ipv4Hdr = tc_ptr_at(&ctx, EthHdr::LEN)?;
+#[inline(always)]
+fn process_ipv4(ctx: &TcContext) -> Result<i32, Reason> {
+    // Truncated packets are passed on like non IP packets
+    let Ok(ipv4hdr) = tc_ptr_at::<Ipv4Hdr>(ctx, EthHdr::LEN) else {
+        return Ok(TC_ACT_OK);
+    };
 
     // Perform lookup in MC Group Map
     let addr = unsafe { u32::from_be_bytes((*ipv4hdr).dst_addr) };
 
-    if let Some(bier_hdr) = IPMC4_TO_BIER.get_ptr_mut(&addr) {
-        info!(&ctx, "Found mapping for address {}", addr);
+    if let Some(entry) = IPMC4_TO_BIER.get_ptr_mut(&addr) {
+        info!(ctx, "Found mapping for address {}", addr);
 
-        
+        let encap = encap_of(ctx, entry)?;
+        if let Some(mtu) = exceeds_mtu(ctx, encap.push_len) {
+            warn!(ctx, "Packet for {} exceeds MTU {} with BIER", addr, mtu);
+            return mtu_exceeded(ctx, entry, mtu.saturating_sub(encap.push_len as u32), false);
+        }
+
+        let entropy = entropy_of(ctx, entry, false);
+        push_bier_hdr(ctx, entry, &encap, entropy)?;
+
+        // Update counter for this mapping
+        if let Some(counter) = v4_EGRESS_COUNTER.get_ptr_mut(&addr) {
+            unsafe { (*counter).add_packet(ctx.len() as u64) };
+        } else {
+            warn!(ctx, "Could not increment counter for: {}! Map not initialized yet?",addr);
+        }
 
-        // Push BIER header, adjust ethertype accordingly
+        return Ok(TC_ACT_OK);
+    }
 
-        // Grow packet size
-        let ret = unsafe { bpf_skb_change_head(ctx.skb.skb, BIER_HEADER_SIZE as u32, 0) };
+    Ok(TC_ACT_OK)
+}
 
-        // TODO ret value handling
+#[inline(always)]
+fn process_ipv6(ctx: &TcContext) -> Result<i32, Reason> {
+    let Ok(ipv6hdr) = tc_ptr_at::<Ipv6Hdr>(ctx, EthHdr::LEN) else {
+        return Ok(TC_ACT_OK);
+    };
 
-        // Move Eth header to new front
-        {
-            // Old eth header, now moved back by BIER header size
-            let ethhdr: *mut EthHdr = tc_ptr_mut_at(&ctx, BIER_HEADER_SIZE)?;
+    // Only IPv6 multicast (ff00::/8) is mapped to BIER
+    let dst_addr = unsafe { (*ipv6hdr).dst_addr };
+    if dst_addr[0] != 0xff {
+        return Ok(TC_ACT_OK);
+    }
 
-            // New eth header, at offset zero
-            let new_ethhdr: *mut EthHdr = tc_ptr_mut_at(&ctx, 0)?;
+    // Perform lookup in MC Group Map
+    let addr = u128::from_be_bytes(dst_addr);
 
-            // Copy old eth header to new location in packet, change ethertype
-            unsafe {
-                memcpy(new_ethhdr as *mut _, ethhdr as *mut _, EthHdr::LEN);
-                (*new_ethhdr).ether_type = BIER_ETHERTYPE;
-            }
-        }
+    if let Some(entry) = IPMC6_TO_BIER.get_ptr_mut(&addr) {
+        info!(ctx, "Found mapping for address {:i}", dst_addr);
 
-        // Write new BIER header
-        let bier_hdr_start: *mut [u8; BIER_HEADER_SIZE] = tc_ptr_mut_at(&ctx, EthHdr::LEN)?;
-        unsafe {
-            memcpy(
-                bier_hdr_start as *mut _,
-                bier_hdr as *mut _,
-                BIER_HEADER_SIZE,
-            );
+        let encap = encap_of(ctx, entry)?;
+        if let Some(mtu) = exceeds_mtu(ctx, encap.push_len) {
+            warn!(ctx, "Packet for {:i} exceeds MTU {} with BIER", dst_addr, mtu);
+            return mtu_exceeded(ctx, entry, mtu.saturating_sub(encap.push_len as u32), true);
         }
 
-        info!(&ctx, "Adding BIER successfull!");
+        let entropy = entropy_of(ctx, entry, true);
+        push_bier_hdr(ctx, entry, &encap, entropy)?;
 
         // Update counter for this mapping
-        if let Some(entry_ptr) = unsafe {v4_EGRESS_COUNTER.get_ptr_mut(&addr)} {
-            // TODO: how bad can this screw up? This is probably not atomic?
-            unsafe {
-                (*entry_ptr) += 1;
-            }
+        if let Some(counter) = v6_EGRESS_COUNTER.get_ptr_mut(&addr) {
+            unsafe { (*counter).add_packet(ctx.len() as u64) };
         } else {
-            warn!(&ctx, "Could not increment counter for: {}! Map not initialized yet?",addr);
+            warn!(ctx, "Could not increment counter for: {:i}! Map not initialized yet?", dst_addr);
         }
 
+        return Ok(TC_ACT_OK);
     }
 
     Ok(TC_ACT_OK)
 }
+
+// Headers pushed in front of the payload for a mapping
+#[derive(Clone, Copy)]
+struct Encap {
+    ether_type: u16,
+    bier_offset: usize,
+    bsl: u8,
+    push_len: usize,
+}
+
+#[inline(always)]
+fn encap_of(ctx: &TcContext, entry: *const EgressEntry) -> Result<Encap, Reason> {
+    let bier_hdr = unsafe { addr_of!((*entry).hdr) };
+    let encap = unsafe { (*entry).encap };
+
+    // MPLS encapsulation uses the same header, BIFT-id is the label
+    // BIERin6 additionally pushes an IPv6 and Destination Options header in front of the BIER header
+    let (ether_type, bier_offset) = match encap {
+        ENCAP_MPLS => (BIER_MPLS_ETHERTYPE, EthHdr::LEN),
+        ENCAP_IPV6 => (EtherType::Ipv6 as u16, EthHdr::LEN + BIERIN6_BIER_OFFSET),
+        _ => (BIER_ETHERTYPE, EthHdr::LEN),
+    };
+
+    // Header length is given by the BSL of the configured header
+    let word1: [u8; 4] = unsafe { *((bier_hdr as *const u8).add(WORD1_OFFSET) as *const [u8; 4]) };
+    let bsl = Word1::from_bytes(word1).bsl();
+    let hdr_len = header_len(bsl);
+    if hdr_len == 0 {
+        warn!(ctx, "Invalid BSL {} in mapping, not adding BIER!", bsl);
+        return Err(Reason::InvalidBsl);
+    }
+    if encap == ENCAP_IPV6 && bsl > BIERIN6_MAX_BSL {
+        warn!(ctx, "BSL {} too large for BIERin6, not adding BIER!", bsl);
+        return Err(Reason::InvalidBsl);
+    }
+
+    Ok(Encap {
+        ether_type,
+        bier_offset,
+        bsl,
+        push_len: bier_offset - EthHdr::LEN + hdr_len,
+    })
+}
+
+// MTU of the interface if the packet does not fit into it with `push_len` additional bytes.
+// GSO packets are segmented later on and always fit.
+#[inline(always)]
+fn exceeds_mtu(ctx: &TcContext, push_len: usize) -> Option<u32> {
+    let mut mtu: u32 = 0;
+    let ret = unsafe { bpf_check_mtu(ctx.skb.skb as *mut _, 0, &mut mtu, push_len as i32, 0) };
+    if ret > BPF_MTU_CHK_RET_SUCCESS as c_long {
+        Some(mtu)
+    } else {
+        None
+    }
+}
+
+// Apply the MTU policy of the mapping, `mtu` is what is left for the packet before encapsulation.
+// Nothing left means the headers alone exceed the MTU, such packets are always dropped.
+#[inline(always)]
+fn mtu_exceeded(ctx: &TcContext, entry: *const EgressEntry, mtu: u32, v6: bool) -> Result<i32, Reason> {
+    if mtu == 0 {
+        return Err(Reason::MtuExceeded);
+    }
+    match unsafe { (*entry).mtu_policy } {
+        MTU_POLICY_PASS => Err(Reason::MtuPassed),
+        MTU_POLICY_ICMP => {
+            // Failures are counted by the caller
+            let ret = if v6 { packet_too_big_v6(ctx, mtu)? } else { packet_too_big_v4(ctx, mtu)? };
+            // Original packet is not sent, so it is counted like a dropped one
+            count_reason(Reason::MtuExceeded);
+            Ok(ret)
+        }
+        _ => Err(Reason::MtuExceeded),
+    }
+}
+
+// Entropy by the mode of the mapping, None keeps the value of the configured header
+#[inline(always)]
+fn entropy_of(ctx: &TcContext, entry: *const EgressEntry, v6: bool) -> Option<u32> {
+    match unsafe { (*entry).entropy_mode } {
+        ENTROPY_FLOW_HASH if v6 => Some(fold_entropy(flow_hash_v6(ctx))),
+        ENTROPY_FLOW_HASH => Some(fold_entropy(flow_hash_v4(ctx))),
+        ENTROPY_RANDOM => Some(fold_entropy(unsafe { bpf_get_prandom_u32() })),
+        _ => None,
+    }
+}
+
+// Hash of the kernel flow dissector, used for packets without TCP or UDP ports
+#[inline(always)]
+fn skb_hash(ctx: &TcContext) -> u32 {
+    unsafe { bpf_get_hash_recalc(ctx.skb.skb) }
+}
+
+// 5-tuple hash for TCP and UDP, fragments only have the ports in the first one
+#[inline(always)]
+fn flow_hash_v4(ctx: &TcContext) -> u32 {
+    let Ok(hdr) = ctx.load::<[u8; 20]>(EthHdr::LEN) else {
+        return skb_hash(ctx);
+    };
+    let proto = hdr[9];
+    let fragment = u16::from_be_bytes([hdr[6], hdr[7]]) & 0x3fff != 0;
+    if (proto != IPPROTO_TCP && proto != IPPROTO_UDP) || fragment {
+        return skb_hash(ctx);
+    }
+
+    let ihl = ((hdr[0] & 0x0f) as usize) * 4;
+    let Ok(ports) = ctx.load::<u32>(EthHdr::LEN + ihl) else {
+        return skb_hash(ctx);
+    };
+    let src = u32::from_be_bytes([hdr[12], hdr[13], hdr[14], hdr[15]]);
+    let dst = u32::from_be_bytes([hdr[16], hdr[17], hdr[18], hdr[19]]);
+    flow_hash(&[src, dst, ports, proto as u32])
+}
+
+// 5-tuple hash for TCP and UDP directly behind the IPv6 header
+#[inline(always)]
+fn flow_hash_v6(ctx: &TcContext) -> u32 {
+    let Ok(hdr) = ctx.load::<[u32; IPV6_HDR_LEN / 4]>(EthHdr::LEN) else {
+        return skb_hash(ctx);
+    };
+    let proto = hdr[1].to_ne_bytes()[IPV6_NEXT_HDR_OFFSET - 4];
+    if proto != IPPROTO_TCP && proto != IPPROTO_UDP {
+        return skb_hash(ctx);
+    }
+
+    let Ok(ports) = ctx.load::<u32>(EthHdr::LEN + IPV6_HDR_LEN) else {
+        return skb_hash(ctx);
+    };
+    // Addresses are words 2 to 9 of the header
+    flow_hash(&[
+        hdr[2], hdr[3], hdr[4], hdr[5], hdr[6], hdr[7], hdr[8], hdr[9], ports, proto as u32,
+    ])
+}
+
+// Push BIER header between Ethernet header and payload, adjust ethertype accordingly
+#[inline(always)]
+fn push_bier_hdr(
+    ctx: &TcContext,
+    entry: *mut EgressEntry,
+    encap: &Encap,
+    entropy: Option<u32>,
+) -> Result<(), Reason> {
+    let bier_hdr = unsafe { addr_of_mut!((*entry).hdr) };
+    let Encap { ether_type, bier_offset, bsl, push_len } = *encap;
+
+    // Grow packet size
+    // Packet is unchanged if growing fails and is passed on without BIER
+    let ret = unsafe { bpf_skb_change_head(ctx.skb.skb, push_len as u32, 0) };
+    if ret != 0 {
+        warn!(ctx, "Failed to grow packet by {} bytes, not adding BIER!", push_len);
+        return Err(Reason::ChangeHeadFailed);
+    }
+
+    // Move Eth header to new front
+    {
+        // Old eth header, now moved back by the pushed headers
+        let ethhdr: *mut EthHdr = tc_ptr_mut_at(ctx, push_len)?;
+
+        // New eth header, at offset zero
+        let new_ethhdr: *mut EthHdr = tc_ptr_mut_at(ctx, 0)?;
+
+        // Copy old eth header to new location in packet, change ethertype
+        unsafe {
+            memcpy(new_ethhdr as *mut _, ethhdr as *mut _, EthHdr::LEN);
+            (*new_ethhdr).ether_type = ether_type;
+        }
+    }
+
+    if unsafe { (*entry).encap } == ENCAP_IPV6 {
+        write_ipv6_hdr(ctx, entry, bsl)?;
+    }
+
+    // Write new BIER header, copy size has to be known at compile time
+    match bsl {
+        1 => write_bier_hdr::<{ header_len(1) }>(ctx, bier_offset, bier_hdr)?,
+        2 => write_bier_hdr::<{ header_len(2) }>(ctx, bier_offset, bier_hdr)?,
+        3 => write_bier_hdr::<{ header_len(3) }>(ctx, bier_offset, bier_hdr)?,
+        4 => write_bier_hdr::<{ header_len(4) }>(ctx, bier_offset, bier_hdr)?,
+        5 => write_bier_hdr::<{ header_len(5) }>(ctx, bier_offset, bier_hdr)?,
+        6 => write_bier_hdr::<{ header_len(6) }>(ctx, bier_offset, bier_hdr)?,
+        _ => write_bier_hdr::<{ header_len(7) }>(ctx, bier_offset, bier_hdr)?,
+    }
+
+    // Per packet entropy replaces the one of the configured header
+    if let Some(entropy) = entropy {
+        let word1: *mut [u8; 4] = tc_ptr_mut_at(ctx, bier_offset + WORD1_OFFSET)?;
+        unsafe { *word1 = Word1::from_bytes(*word1).with_entropy(entropy).into_bytes() };
+    }
+
+    info!(ctx, "Adding BIER successfull!");
+
+    Ok(())
+}
+
+// Write outer IPv6 and Destination Options header of BIERin6 and set the MAC addresses for its destination
+#[inline(always)]
+fn write_ipv6_hdr(ctx: &TcContext, entry: *const EgressEntry, bsl: u8) -> Result<(), Reason> {
+    let payload_len = (ctx.len() as usize - EthHdr::LEN - IPV6_HDR_LEN) as u16;
+    let word2: [u8; 4] = unsafe { *((addr_of!((*entry).hdr) as *const u8).add(WORD2_OFFSET) as *const [u8; 4]) };
+    let proto = Word2::from_bytes(word2).proto();
+
+    // Version 6, no traffic class and flow label
+    let mut ip6 = [0u8; IPV6_HDR_LEN];
+    ip6[0] = 0x60;
+    ip6[4..IPV6_NEXT_HDR_OFFSET].copy_from_slice(&payload_len.to_be_bytes());
+    ip6[IPV6_NEXT_HDR_OFFSET] = NEXT_HDR_DEST_OPTS;
+    ip6[IPV6_NEXT_HDR_OFFSET + 1] = IPV6_HOP_LIMIT;
+    unsafe {
+        ip6[IPV6_SRC_OFFSET..IPV6_DST_OFFSET].copy_from_slice(&(*entry).src);
+        ip6[IPV6_DST_OFFSET..].copy_from_slice(&(*entry).dst);
+    }
+
+    let ip6_start: *mut [u8; IPV6_HDR_LEN] = tc_ptr_mut_at(ctx, EthHdr::LEN)?;
+    let opts_start: *mut [u8; DEST_OPTS_HDR_LEN] = tc_ptr_mut_at(ctx, EthHdr::LEN + IPV6_HDR_LEN)?;
+    unsafe {
+        *ip6_start = ip6;
+        *opts_start = [
+            bierin6::next_header(proto),
+            dest_opts_ext_len(bsl),
+            BIERIN6_OPTION_TYPE,
+            header_len(bsl) as u8,
+        ];
+    }
+
+    set_ipv6_macs(ctx, entry)
+}
+
+// Multicast destinations use the derived MAC, unicast destinations are resolved by a FIB lookup.
+// If the lookup fails, the MAC addresses of the original frame are kept.
+#[inline(always)]
+fn set_ipv6_macs(ctx: &TcContext, entry: *const EgressEntry) -> Result<(), Reason> {
+    let ethhdr: *mut EthHdr = tc_ptr_mut_at(ctx, 0)?;
+    let dst = unsafe { (*entry).dst };
+
+    if dst[0] == 0xff {
+        unsafe {
+            (*ethhdr).dst_addr = [0x33, 0x33, dst[12], dst[13], dst[14], dst[15]];
+        }
+        return Ok(());
+    }
+
+    let mut params: FibParams = unsafe { mem::zeroed() };
+    params.family = AF_INET6;
+    params.ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    unsafe {
+        params.__bindgen_anon_3.ipv6_src = mem::transmute::<[u8; 16], [u32; 4]>((*entry).src);
+        params.__bindgen_anon_4.ipv6_dst = mem::transmute::<[u8; 16], [u32; 4]>(dst);
+    }
+
+    let ret = unsafe {
+        bpf_fib_lookup(
+            ctx.skb.skb as *mut _,
+            &mut params,
+            mem::size_of::<FibParams>() as c_int,
+            BPF_FIB_LOOKUP_OUTPUT,
+        )
+    };
+    if ret != BPF_FIB_LKUP_RET_SUCCESS as c_long {
+        warn!(ctx, "No neighbor for BIERin6 destination {:i}, keeping MAC addresses", dst);
+        return Ok(());
+    }
+
+    unsafe {
+        (*ethhdr).dst_addr = params.dmac;
+        (*ethhdr).src_addr = params.smac;
+    }
+
+    Ok(())
+}
+
+#[inline(always)]
+fn write_bier_hdr<const N: usize>(ctx: &TcContext, offset: usize, bier_hdr: *mut [u8; BIER_HEADER_SIZE]) -> Result<(), Reason> {
+    let bier_hdr_start: *mut [u8; N] = tc_ptr_mut_at(ctx, offset)?;
+    unsafe {
+        memcpy(
+            bier_hdr_start as *mut _,
+            bier_hdr as *mut _,
+            N,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,542 @@
+use core::mem::offset_of;
+
+use aya_ebpf::{
+    bindings::{__sk_buff, BPF_CSUM_LEVEL_RESET, BPF_F_INGRESS, TC_ACT_OK, TC_ACT_SHOT},
+    cty::{c_long, c_void},
+    helpers::{
+        bpf_redirect,
+        r#gen::{bpf_csum_level, bpf_loop, bpf_skb_change_tail, bpf_skb_load_bytes, bpf_skb_store_bytes},
+    },
+    macros::map,
+    maps::{HashMap, PerCpuArray},
+    programs::TcContext,
+};
+use aya_log_ebpf::{error, info, warn};
+use network_types::{
+    eth::EtherType,
+    ip::{Ipv4Hdr, Ipv6Hdr},
+};
+
+use bier_common::{
+    bier::{
+        bitstring_len, header_len, Word0, Word1, Word2, BIER_ETHERTYPE, BIER_MPLS_ETHERTYPE,
+        MAX_BITSTRING_LEN, WORD0_OFFSET, WORD1_OFFSET, WORD2_OFFSET,
+    },
+    bift::{
+        bit_to_bfr_id, BiftEntry, BiftInfo, BiftKey, BiftNeighbor, TeAdjacency, ADJ_ECMP,
+        ADJ_FORWARD_CONNECTED, ADJ_FORWARD_ROUTED, ADJ_LOCAL_DECAP, BIFT_MODE_TE, MAX_ECMP,
+    },
+    reason::Reason,
+};
+
+use crate::{
+    ingress::{count_decap, inner_ether_type, is_mpls_bier, local_bfer, InnerDst, BS_OFFSET},
+    reason::count_reason,
+    ttl::decrement_ttl,
+    util::EthHdr,
+};
+
+#[map]
+static BIFT: HashMap<BiftKey, BiftEntry> = HashMap::<BiftKey, BiftEntry>::with_max_entries(4096, 0);
+
+// Per BIFT-id settings, BIFT-ids without entry use BIER forwarding
+#[map]
+pub static BIFT_INFO: HashMap<u32, BiftInfo> = HashMap::<u32, BiftInfo>::with_max_entries(256, 0);
+
+// BIER-TE adjacencies by bit position
+#[map]
+static BIFT_TE: HashMap<BiftKey, TeAdjacency> = HashMap::<BiftKey, TeAdjacency>::with_max_entries(4096, 0);
+
+// Working copies of the bitstring, too large for the stack with 4096 bits
+#[repr(C)]
+pub struct ForwardScratch {
+    remaining: [u8; MAX_BITSTRING_LEN],
+    bitstring: [u8; MAX_BITSTRING_LEN],
+}
+
+#[map]
+static FORWARD_SCRATCH: PerCpuArray<ForwardScratch> = PerCpuArray::<ForwardScratch>::with_max_entries(1, 0);
+
+#[no_mangle]
+// Set if the BIFT is configured, XDP ingress passes BIER packets with non-local bits to this program
+pub static mut BFR_FORWARDING: u8 = 0;
+
+// State passed to the replication loop
+struct Replication {
+    skb: *mut __sk_buff,
+    bift_id: u32,
+    bs_len: usize,
+}
+
+// State passed to the BIER-TE adjacency loop
+struct TeReplication {
+    skb: *mut __sk_buff,
+    bift_id: u32,
+    bs_len: usize,
+    entropy: u32,
+    forward: bool,
+    decap: bool,
+}
+
+// State passed to the loop that moves the decapsulated frame to the front of the packet
+struct Shift {
+    skb: *mut __sk_buff,
+    // Offset of the decapsulated frame and its length
+    from: u32,
+    len: u32,
+    failed: bool,
+}
+
+// Bytes moved per iteration of the shift loop
+const SHIFT_CHUNK: u32 = 64;
+
+// Transit BFR processing according to: https://datatracker.ietf.org/doc/html/rfc8279#section-6.5
+#[inline(always)]
+pub fn process_forward(mut ctx: TcContext) -> Result<i32, Reason> {
+    let ethertype: u16 = ctx.load(offset_of!(EthHdr, ether_type)).map_err(|_| Reason::LoadStoreFailed)?;
+
+    // BIER packets for local BFER only are already decapsulated in XDP
+    if ethertype != BIER_ETHERTYPE && ethertype != BIER_MPLS_ETHERTYPE {
+        return Ok(TC_ACT_OK);
+    }
+
+    let word0: [u8; 4] = ctx.load(EthHdr::LEN + WORD0_OFFSET).map_err(|_| Reason::LoadStoreFailed)?;
+    let word0 = Word0::from_bytes(word0);
+    let bift_id = word0.bift_id();
+
+    let word1: [u8; 4] = ctx.load(EthHdr::LEN + WORD1_OFFSET).map_err(|_| Reason::LoadStoreFailed)?;
+    let word1 = Word1::from_bytes(word1);
+
+    if ethertype == BIER_MPLS_ETHERTYPE && !is_mpls_bier(bift_id, word1.nibble()) {
+        return Ok(TC_ACT_OK);
+    }
+
+    let bsl = word1.bsl();
+    let bs_len = bitstring_len(bsl);
+    if bs_len == 0 {
+        error!(&ctx, "Invalid BSL {}, dropping packet!", bsl);
+        return Err(Reason::InvalidBsl);
+    }
+
+    if let Some(info) = unsafe { BIFT_INFO.get(&bift_id) } {
+        if info.mode == BIFT_MODE_TE {
+            return process_te(ctx, word0, bsl, word1.entropy(), info);
+        }
+    }
+
+    let scratch = FORWARD_SCRATCH.get_ptr_mut(0).ok_or(Reason::MapLookupFailed)?;
+    let remaining = unsafe { &mut (*scratch).remaining };
+
+    let ret = unsafe {
+        bpf_skb_load_bytes(
+            ctx.skb.skb as *const _,
+            (EthHdr::LEN + BS_OFFSET) as u32,
+            remaining.as_mut_ptr() as *mut _,
+            bs_len as u32,
+        )
+    };
+    if ret != 0 {
+        return Err(Reason::LoadStoreFailed);
+    }
+
+    // Local bits are served by the decapsulated copy and never forwarded
+    let local_bfer = local_bfer()?;
+    let local_offset = MAX_BITSTRING_LEN - bs_len;
+    let mut bit_match = false;
+    let mut foreign_bits = false;
+    for i in 0..MAX_BITSTRING_LEN {
+        if i >= bs_len {
+            break;
+        }
+        let local = match local_bfer.get(local_offset + i) {
+            Some(local) => *local,
+            None => 0,
+        };
+        if remaining[i] & local > 0 {
+            bit_match = true;
+        }
+        remaining[i] &= !local;
+        if remaining[i] > 0 {
+            foreign_bits = true;
+        }
+    }
+
+    // Packets without matching bit were passed by the no match policy in XDP and are already counted
+    if !bit_match && (!foreign_bits || unsafe { BFR_FORWARDING } == 0) {
+        return Ok(TC_ACT_OK);
+    }
+
+    // Neighbors rewrite the Ethernet addresses, keep the original ones for local delivery
+    let macs: [u8; 12] = ctx.load(0).map_err(|_| Reason::LoadStoreFailed)?;
+
+    let forward = decrement_ttl(&mut ctx, EthHdr::LEN, word0)?;
+    if forward {
+        // Bounded loop over all bits would exceed the verifier complexity limit with 4096 bits
+        let mut replication = Replication {
+            skb: ctx.skb.skb,
+            bift_id,
+            bs_len,
+        };
+        unsafe {
+            bpf_loop(
+                (bs_len * 8) as u32,
+                replicate_bit as *const () as *mut c_void,
+                &mut replication as *mut _ as *mut c_void,
+                0,
+            )
+        };
+    }
+
+    // Original packet is consumed by the copies, it is only lost if the TTL expired
+    if !bit_match {
+        return if forward { Ok(TC_ACT_SHOT) } else { Err(Reason::TtlExpired) };
+    }
+
+    ctx.store(0, &macs, 0).map_err(|_| Reason::LoadStoreFailed)?;
+    decapsulate(&mut ctx, EthHdr::LEN, header_len(bsl))
+}
+
+// Send a copy for the bit with the given index if it is still set in the remaining bitstring
+extern "C" fn replicate_bit(index: u64, data: *mut c_void) -> c_long {
+    let replication = unsafe { &*(data as *const Replication) };
+    let ctx = TcContext::new(replication.skb);
+
+    let bs_len = replication.bs_len;
+    if bs_len == 0 || bs_len > MAX_BITSTRING_LEN {
+        return 1;
+    }
+
+    let Some(scratch) = FORWARD_SCRATCH.get_ptr_mut(0) else {
+        return 1;
+    };
+    let scratch = unsafe { &mut *scratch };
+
+    let i = (index / 8) as usize;
+    let j = (index % 8) as usize;
+    let mask = 0x80 >> j;
+
+    let Some(byte) = scratch.remaining.get(i) else {
+        return 1;
+    };
+    if byte & mask == 0 {
+        return 0;
+    }
+
+    let key = BiftKey {
+        bift_id: replication.bift_id,
+        bfr_id: bit_to_bfr_id(i, j, bs_len),
+    };
+
+    let Some(nbr) = (unsafe { BIFT.get(&key) }) else {
+        warn!(&ctx, "No BIFT entry for BFR-id {}, clearing bit", key.bfr_id);
+        scratch.remaining[i] &= !mask;
+        return 0;
+    };
+
+    // Copy only carries the bits reachable over this neighbor
+    let fbm_offset = MAX_BITSTRING_LEN - bs_len;
+    for k in 0..MAX_BITSTRING_LEN {
+        if k >= bs_len {
+            break;
+        }
+        let fbm = match nbr.fbm.get(fbm_offset + k) {
+            Some(fbm) => *fbm,
+            None => 0,
+        };
+        scratch.bitstring[k] = scratch.remaining[k] & fbm;
+        scratch.remaining[k] &= !fbm;
+    }
+    // In case of a F-BM that does not contain its own BFR-id
+    scratch.remaining[i] &= !mask;
+
+    let ret = unsafe {
+        bpf_skb_store_bytes(
+            replication.skb as *mut _,
+            (EthHdr::LEN + BS_OFFSET) as u32,
+            scratch.bitstring.as_ptr() as *const _,
+            bs_len as u32,
+            0,
+        )
+    };
+    if ret != 0 {
+        count_reason(Reason::LoadStoreFailed);
+        return 1;
+    }
+
+    if let Err(reason) = send_copy(ctx, &nbr.nbr) {
+        count_reason(reason);
+        return 1;
+    }
+
+    0
+}
+
+// BIER-TE processing according to: https://datatracker.ietf.org/doc/html/rfc9262#section-4.3
+#[inline(always)]
+fn process_te(mut ctx: TcContext, word0: Word0, bsl: u8, entropy: u32, info: &BiftInfo) -> Result<i32, Reason> {
+    let bift_id = word0.bift_id();
+    let bs_len = bitstring_len(bsl);
+
+    let scratch = FORWARD_SCRATCH.get_ptr_mut(0).ok_or(Reason::MapLookupFailed)?;
+    let scratch = unsafe { &mut *scratch };
+
+    let ret = unsafe {
+        bpf_skb_load_bytes(
+            ctx.skb.skb as *const _,
+            (EthHdr::LEN + BS_OFFSET) as u32,
+            scratch.remaining.as_mut_ptr() as *mut _,
+            bs_len as u32,
+        )
+    };
+    if ret != 0 {
+        return Err(Reason::LoadStoreFailed);
+    }
+
+    // Adjacencies of this BFR are processed here and cleared in all copies
+    let adj_offset = MAX_BITSTRING_LEN - bs_len;
+    for i in 0..MAX_BITSTRING_LEN {
+        if i >= bs_len {
+            break;
+        }
+        let adjacent = match info.adjacent_bits.get(adj_offset + i) {
+            Some(adjacent) => *adjacent,
+            None => 0,
+        };
+        scratch.bitstring[i] = scratch.remaining[i] & !adjacent;
+        scratch.remaining[i] &= adjacent;
+    }
+
+    let ret = unsafe {
+        bpf_skb_store_bytes(
+            ctx.skb.skb as *mut _,
+            (EthHdr::LEN + BS_OFFSET) as u32,
+            scratch.bitstring.as_ptr() as *const _,
+            bs_len as u32,
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(Reason::LoadStoreFailed);
+    }
+
+    // Neighbors rewrite the Ethernet addresses, keep the original ones for local delivery
+    let macs: [u8; 12] = ctx.load(0).map_err(|_| Reason::LoadStoreFailed)?;
+
+    // Local adjacencies are still processed if the TTL expires
+    let mut replication = TeReplication {
+        skb: ctx.skb.skb,
+        bift_id,
+        bs_len,
+        entropy,
+        forward: decrement_ttl(&mut ctx, EthHdr::LEN, word0)?,
+        decap: false,
+    };
+    unsafe {
+        bpf_loop(
+            (bs_len * 8) as u32,
+            te_replicate_bit as *const () as *mut c_void,
+            &mut replication as *mut _ as *mut c_void,
+            0,
+        )
+    };
+
+    if !replication.decap {
+        return if replication.forward { Ok(TC_ACT_SHOT) } else { Err(Reason::TtlExpired) };
+    }
+
+    ctx.store(0, &macs, 0).map_err(|_| Reason::LoadStoreFailed)?;
+    decapsulate(&mut ctx, EthHdr::LEN, header_len(bsl))
+}
+
+// Process the adjacency of the bit with the given index if it is set in the packet
+extern "C" fn te_replicate_bit(index: u64, data: *mut c_void) -> c_long {
+    let replication = unsafe { &mut *(data as *mut TeReplication) };
+    let ctx = TcContext::new(replication.skb);
+
+    let bs_len = replication.bs_len;
+    if bs_len == 0 || bs_len > MAX_BITSTRING_LEN {
+        return 1;
+    }
+
+    let Some(scratch) = FORWARD_SCRATCH.get_ptr(0) else {
+        return 1;
+    };
+    let scratch = unsafe { &*scratch };
+
+    let i = (index / 8) as usize;
+    let j = (index % 8) as usize;
+
+    let Some(byte) = scratch.remaining.get(i) else {
+        return 1;
+    };
+    if byte & (0x80 >> j) == 0 {
+        return 0;
+    }
+
+    let key = BiftKey {
+        bift_id: replication.bift_id,
+        bfr_id: bit_to_bfr_id(i, j, bs_len),
+    };
+
+    let Some(adj) = (unsafe { BIFT_TE.get(&key) }) else {
+        warn!(&ctx, "No adjacency for bit {} in BIFT-id {}", key.bfr_id, key.bift_id);
+        return 0;
+    };
+
+    let nbr = match adj.kind {
+        ADJ_LOCAL_DECAP => {
+            replication.decap = true;
+            return 0;
+        }
+        _ if !replication.forward => return 0,
+        ADJ_FORWARD_CONNECTED | ADJ_FORWARD_ROUTED => adj.nbrs.first(),
+        ADJ_ECMP => {
+            let num_nbrs = adj.num_nbrs as u32;
+            if num_nbrs == 0 || num_nbrs > MAX_ECMP as u32 {
+                return 0;
+            }
+            adj.nbrs.get((replication.entropy % num_nbrs) as usize)
+        }
+        _ => None,
+    };
+
+    if let Some(nbr) = nbr {
+        if let Err(reason) = send_copy(ctx, nbr) {
+            count_reason(reason);
+            return 1;
+        }
+    }
+
+    0
+}
+
+// Rewrite Ethernet addresses and send a copy of the packet to the neighbor
+#[inline(always)]
+fn send_copy(mut ctx: TcContext, nbr: &BiftNeighbor) -> Result<(), Reason> {
+    ctx.store(offset_of!(EthHdr, dst_addr), &nbr.nh_mac, 0).map_err(|_| Reason::LoadStoreFailed)?;
+    ctx.store(offset_of!(EthHdr, src_addr), &nbr.src_mac, 0).map_err(|_| Reason::LoadStoreFailed)?;
+
+    // Only this copy is lost, the remaining neighbors still get theirs
+    if ctx.clone_redirect(nbr.ifindex, 0).is_err() {
+        error!(&ctx, "Failed to forward copy to ifindex {}", nbr.ifindex);
+        count_reason(Reason::CloneRedirectFailed);
+    } else {
+        info!(&ctx, "Forwarded copy to ifindex {}", nbr.ifindex);
+    }
+
+    Ok(())
+}
+
+// Parse the destination of the decapsulated frame
+#[inline(always)]
+fn inner_dst(ctx: &TcContext) -> InnerDst {
+    let Ok(ether_type) = ctx.load::<u16>(offset_of!(EthHdr, ether_type)) else {
+        return InnerDst::Unknown;
+    };
+
+    if ether_type == EtherType::Ipv4 as u16 {
+        match ctx.load::<[u8; 4]>(EthHdr::LEN + offset_of!(Ipv4Hdr, dst_addr)) {
+            Ok(dst) => InnerDst::V4(u32::from_be_bytes(dst)),
+            Err(_) => InnerDst::Unknown,
+        }
+    } else if ether_type == EtherType::Ipv6 as u16 {
+        match ctx.load::<[u8; 16]>(EthHdr::LEN + offset_of!(Ipv6Hdr, dst_addr)) {
+            Ok(dst) => InnerDst::V6(u128::from_be_bytes(dst)),
+            Err(_) => InnerDst::Unknown,
+        }
+    } else {
+        InnerDst::Unknown
+    }
+}
+
+// Move one chunk of the decapsulated frame to the front of the packet
+extern "C" fn shift_chunk(index: u64, data: *mut c_void) -> c_long {
+    let shift = unsafe { &mut *(data as *mut Shift) };
+    let offset = index as u32 * SHIFT_CHUNK;
+    if offset >= shift.len {
+        return 1;
+    }
+
+    // Only the last chunk is shorter, the bound keeps the verifier happy
+    let len = (shift.len - offset).min(SHIFT_CHUNK);
+    if len == 0 {
+        return 1;
+    }
+    let mut chunk = [0u8; SHIFT_CHUNK as usize];
+    let ret = unsafe {
+        bpf_skb_load_bytes(shift.skb as *const _, shift.from + offset, chunk.as_mut_ptr() as *mut _, len)
+    };
+    if ret != 0 || unsafe { bpf_skb_store_bytes(shift.skb, offset, chunk.as_ptr() as *const _, len, 0) } != 0 {
+        shift.failed = true;
+        return 1;
+    }
+    0
+}
+
+// Remove the first `len` bytes of the packet. bpf_skb_adjust_room only shrinks IPv4 and IPv6
+// packets, so the rest of a BIER or MPLS packet is moved to the front and the tail trimmed.
+#[inline(always)]
+fn strip_head(ctx: &mut TcContext, len: usize) -> Result<(), Reason> {
+    let mut shift = Shift {
+        skb: ctx.skb.skb,
+        from: len as u32,
+        len: ctx.len().saturating_sub(len as u32),
+        failed: false,
+    };
+    let ret = unsafe {
+        bpf_loop(
+            shift.len.div_ceil(SHIFT_CHUNK),
+            shift_chunk as *const () as *mut c_void,
+            &mut shift as *mut _ as *mut c_void,
+            0,
+        )
+    };
+    if ret < 0 || shift.failed {
+        return Err(Reason::LoadStoreFailed);
+    }
+    if unsafe { bpf_skb_change_tail(ctx.skb.skb, shift.len, 0) } != 0 {
+        return Err(Reason::AdjustHeadFailed);
+    }
+
+    // The checksum of the received packet no longer matches, the stack verifies it again
+    unsafe { bpf_csum_level(ctx.skb.skb, BPF_CSUM_LEVEL_RESET as u64) };
+    Ok(())
+}
+
+// Remove the `outer_len` bytes of BIER (and BIERin6) headers and reinject the packet into the receive
+// path of the incoming interface.
+// The stack already classified the packet as BIER, so it has to pass eth_type_trans again.
+#[inline(always)]
+pub fn decapsulate(ctx: &mut TcContext, bier_offset: usize, outer_len: usize) -> Result<i32, Reason> {
+    let word0: [u8; 4] = ctx.load(bier_offset + WORD0_OFFSET).map_err(|_| Reason::LoadStoreFailed)?;
+    let bift_id = Word0::from_bytes(word0).bift_id();
+    let word2: [u8; 4] = ctx.load(bier_offset + WORD2_OFFSET).map_err(|_| Reason::LoadStoreFailed)?;
+    let word2 = Word2::from_bytes(word2);
+    let proto = word2.proto();
+
+    // Offset of the frame that is left after decapsulation
+    let start = match inner_ether_type(proto) {
+        Ok(Some(ether_type)) => {
+            // Outer Ethernet addresses and the inner ethertype right in front of the payload
+            let macs: [u8; 12] = ctx.load(0).map_err(|_| Reason::LoadStoreFailed)?;
+            ctx.store(outer_len, &macs, 0).map_err(|_| Reason::LoadStoreFailed)?;
+            ctx.store(outer_len + offset_of!(EthHdr, ether_type), &ether_type, 0)
+                .map_err(|_| Reason::LoadStoreFailed)?;
+            outer_len
+        }
+        // Payload is a complete Ethernet frame
+        Ok(None) => EthHdr::LEN + outer_len,
+        Err(reason) => {
+            error!(ctx, "Unknown BIER next protocol {}, dropping packet!", proto);
+            return Err(reason);
+        }
+    };
+    strip_head(ctx, start)?;
+
+    count_decap(bift_id, word2.bfir_id(), inner_dst(ctx), ctx.len() as u64);
+
+    info!(ctx, "Successfully decapsulated BIER packet!");
+
+    let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    Ok(unsafe { bpf_redirect(ifindex, BPF_F_INGRESS as u64) } as i32)
+}
@@ -0,0 +1,138 @@
+// "Packet too big" for IP multicast that does not fit into the MTU once BIER is pushed.
+// The packet is turned into an ICMP error to its local sender and redirected to the receive path of
+// the interface, so the socket learns the MTU that is left for the payload.
+//
+//     +-----+-------------------------+---------------+--------------------------------------+
+//     | Eth | IP (src = dst = sender) | ICMP too big  | original IP header + 8 bytes payload |
+//     +-----+-------------------------+---------------+--------------------------------------+
+
+use aya_ebpf::{
+    bindings::BPF_F_INGRESS,
+    helpers::{bpf_redirect, r#gen::bpf_skb_change_tail},
+    programs::TcContext,
+};
+use aya_log_ebpf::{info, warn};
+
+use bier_common::{
+    bierin6::{IPV6_DST_OFFSET, IPV6_HDR_LEN, IPV6_HOP_LIMIT, IPV6_NEXT_HDR_OFFSET, IPV6_SRC_OFFSET},
+    reason::Reason,
+};
+
+use crate::util::{csum_add, csum_fold, tc_ptr_mut_at, EthHdr};
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_ICMPV6: u8 = 58;
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_FRAG_NEEDED: u8 = 4;
+const ICMPV6_PKT_TOOBIG: u8 = 2;
+const ICMP_HDR_LEN: usize = 8;
+
+// IPv4 header without options, packets with options are dropped instead
+const IPV4_HDR_LEN: usize = 20;
+const IPV4_VERSION_IHL: u8 = 0x45;
+const IPV4_SRC_OFFSET: usize = 12;
+const IPV4_DST_OFFSET: usize = 16;
+const IPV4_TTL: u8 = 64;
+// Internetwork control, like ICMP errors of the kernel
+const IPV4_TOS_ICMP: u8 = 0xc0;
+
+// Quoting the transport ports is enough for the stack to find the socket
+const V4_QUOTE_LEN: usize = IPV4_HDR_LEN + 8;
+const V6_QUOTE_LEN: usize = IPV6_HDR_LEN + 8;
+
+#[inline(always)]
+pub fn packet_too_big_v4(ctx: &TcContext, mtu: u32) -> Result<i32, Reason> {
+    let eth: EthHdr = ctx.load(0).map_err(|_| Reason::LoadStoreFailed)?;
+    let quote: [u8; V4_QUOTE_LEN] = ctx.load(EthHdr::LEN).map_err(|_| Reason::MtuExceeded)?;
+    if quote[0] != IPV4_VERSION_IHL {
+        warn!(ctx, "No packet too big for IPv4 with options, dropping packet!");
+        return Err(Reason::MtuExceeded);
+    }
+
+    let mut ip = [0u8; IPV4_HDR_LEN];
+    ip[0] = IPV4_VERSION_IHL;
+    ip[1] = IPV4_TOS_ICMP;
+    ip[2..4].copy_from_slice(&((IPV4_HDR_LEN + ICMP_HDR_LEN + V4_QUOTE_LEN) as u16).to_be_bytes());
+    ip[8] = IPV4_TTL;
+    ip[9] = IPPROTO_ICMP;
+    ip[IPV4_SRC_OFFSET..IPV4_DST_OFFSET].copy_from_slice(&quote[IPV4_SRC_OFFSET..IPV4_DST_OFFSET]);
+    ip[IPV4_DST_OFFSET..].copy_from_slice(&quote[IPV4_SRC_OFFSET..IPV4_DST_OFFSET]);
+    let csum = csum_fold(csum_add(0, &ip));
+    ip[10..12].copy_from_slice(&csum.to_be_bytes());
+
+    // Next-hop MTU in the lower half of the unused word (RFC 1191)
+    let mut icmp = [0u8; ICMP_HDR_LEN];
+    icmp[0] = ICMP_DEST_UNREACH;
+    icmp[1] = ICMP_FRAG_NEEDED;
+    icmp[6..8].copy_from_slice(&(mtu as u16).to_be_bytes());
+    let csum = csum_fold(csum_add(csum_add(0, &icmp), &quote));
+    icmp[2..4].copy_from_slice(&csum.to_be_bytes());
+
+    write_packet_too_big(ctx, eth, &ip, &icmp, &quote)
+}
+
+#[inline(always)]
+pub fn packet_too_big_v6(ctx: &TcContext, mtu: u32) -> Result<i32, Reason> {
+    let eth: EthHdr = ctx.load(0).map_err(|_| Reason::LoadStoreFailed)?;
+    let quote: [u8; V6_QUOTE_LEN] = ctx.load(EthHdr::LEN).map_err(|_| Reason::MtuExceeded)?;
+    let payload_len = (ICMP_HDR_LEN + V6_QUOTE_LEN) as u16;
+
+    let mut ip6 = [0u8; IPV6_HDR_LEN];
+    ip6[0] = 0x60;
+    ip6[4..IPV6_NEXT_HDR_OFFSET].copy_from_slice(&payload_len.to_be_bytes());
+    ip6[IPV6_NEXT_HDR_OFFSET] = IPPROTO_ICMPV6;
+    ip6[IPV6_NEXT_HDR_OFFSET + 1] = IPV6_HOP_LIMIT;
+    ip6[IPV6_SRC_OFFSET..IPV6_DST_OFFSET].copy_from_slice(&quote[IPV6_SRC_OFFSET..IPV6_DST_OFFSET]);
+    ip6[IPV6_DST_OFFSET..].copy_from_slice(&quote[IPV6_SRC_OFFSET..IPV6_DST_OFFSET]);
+
+    let mut icmp = [0u8; ICMP_HDR_LEN];
+    icmp[0] = ICMPV6_PKT_TOOBIG;
+    icmp[4..8].copy_from_slice(&mtu.to_be_bytes());
+
+    // Pseudo header of source and destination address, upper-layer length and next header (RFC 8200)
+    let mut pseudo = [0u8; 8];
+    pseudo[2..4].copy_from_slice(&payload_len.to_be_bytes());
+    pseudo[7] = IPPROTO_ICMPV6;
+    let mut addrs = [0u8; 32];
+    addrs.copy_from_slice(&ip6[IPV6_SRC_OFFSET..]);
+    let sum = csum_add(csum_add(csum_add(0, &addrs), &pseudo), &icmp);
+    let csum = csum_fold(csum_add(sum, &quote));
+    icmp[2..4].copy_from_slice(&csum.to_be_bytes());
+
+    write_packet_too_big(ctx, eth, &ip6, &icmp, &quote)
+}
+
+// Replace the packet with the ICMP error and hand it to the local stack.
+// MAC addresses are swapped so the frame is addressed to the interface itself.
+#[inline(always)]
+fn write_packet_too_big<const H: usize, const Q: usize>(
+    ctx: &TcContext,
+    eth: EthHdr,
+    ip: &[u8; H],
+    icmp: &[u8; ICMP_HDR_LEN],
+    quote: &[u8; Q],
+) -> Result<i32, Reason> {
+    let len = EthHdr::LEN + H + ICMP_HDR_LEN + Q;
+    let ret = unsafe { bpf_skb_change_tail(ctx.skb.skb, len as u32, 0) };
+    if ret != 0 {
+        warn!(ctx, "Failed to resize packet for packet too big, dropping packet!");
+        return Err(Reason::MtuExceeded);
+    }
+
+    let eth_start: *mut EthHdr = tc_ptr_mut_at(ctx, 0)?;
+    let ip_start: *mut [u8; H] = tc_ptr_mut_at(ctx, EthHdr::LEN)?;
+    let icmp_start: *mut [u8; ICMP_HDR_LEN] = tc_ptr_mut_at(ctx, EthHdr::LEN + H)?;
+    let quote_start: *mut [u8; Q] = tc_ptr_mut_at(ctx, EthHdr::LEN + H + ICMP_HDR_LEN)?;
+    unsafe {
+        (*eth_start).dst_addr = eth.src_addr;
+        (*eth_start).src_addr = eth.dst_addr;
+        *ip_start = *ip;
+        *icmp_start = *icmp;
+        *quote_start = *quote;
+    }
+
+    info!(ctx, "Sent packet too big to local sender");
+
+    let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    Ok(unsafe { bpf_redirect(ifindex, BPF_F_INGRESS as u64) } as i32)
+}
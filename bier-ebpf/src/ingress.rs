@@ -1,84 +1,376 @@
+use core::mem::offset_of;
+
 use aya_ebpf::bindings::TC_ACT_SHOT;
+use aya_ebpf::bindings::TC_ACT_OK;
 use aya_ebpf::cty::c_int;
-use aya_ebpf::helpers::r#gen::bpf_xdp_adjust_head;
+use aya_ebpf::helpers::{bpf_redirect, r#gen::bpf_xdp_adjust_head};
 use aya_ebpf::macros::map;
-use aya_ebpf::maps::{HashMap, PerCpuArray};
+use aya_ebpf::maps::{Array, HashMap, PerCpuHashMap};
 use aya_ebpf::memcpy;
-use aya_ebpf::{bindings::xdp_action, programs::XdpContext};
+use aya_ebpf::{
+    bindings::xdp_action,
+    programs::{TcContext, XdpContext},
+};
 use aya_log_ebpf::{error, info};
-use network_types::eth::{EthHdr, EtherType};
+use network_types::{
+    eth::EtherType,
+    ip::{Ipv4Hdr, Ipv6Hdr},
+};
 
-use bier_common::bier::{BierHdr, BIER_ETHERTYPE, BIER_HEADER_SIZE};
+use bier_common::{
+    bier::{
+        bitstring_len, header_len, BierHdr, Word0, Word1, Word2, BIER_ETHERTYPE,
+        BIER_MPLS_ETHERTYPE, BIER_PROTO_ETHERNET, BIER_PROTO_IPV4, BIER_PROTO_IPV6,
+        MAX_BITSTRING_LEN, MPLS_BIER_NIBBLE, WORD0_OFFSET, WORD1_OFFSET, WORD2_OFFSET,
+    },
+    bierin6::{
+        BIERIN6_BIER_OFFSET, BIERIN6_MAX_BSL, BIERIN6_OPTION_TYPE, DEST_OPTS_TYPE_OFFSET,
+        IPV6_HDR_LEN, IPV6_NEXT_HDR_OFFSET, NEXT_HDR_DEST_OPTS,
+    },
+    bift::{BIFT_MODE_TE, NO_MATCH_PASS, NO_MATCH_REDIRECT},
+    counter::Counter,
+    reason::Reason,
+};
 
-use crate::util::{ptr_at, ptr_mut_at};
+use crate::forward::{decapsulate, process_forward, BFR_FORWARDING, BIFT_INFO};
+use crate::reason::count_reason;
+use crate::ttl::decrement_ttl;
+use crate::util::{count_packet, ptr_at, ptr_mut_at, xdp_len, EthHdr, Packet};
 use crate::NUM_CPUS;
 
+// Decapsulated packets by inner destination address
+#[map]
+static v4_INGRESS_COUNTER: PerCpuHashMap<u32, Counter> =
+    PerCpuHashMap::<u32, Counter>::with_max_entries(1024, 0);
+#[map]
+static v6_INGRESS_COUNTER: PerCpuHashMap<u128, Counter> =
+    PerCpuHashMap::<u128, Counter>::with_max_entries(1024, 0);
+
+// Decapsulated packets by BFIR-id and BIFT-id
 #[map]
-static mut v4_INGRESS_COUNTER: HashMap<u32, u64> = HashMap::<u32, u64>::with_max_entries(1024, 0);
+static BFIR_INGRESS_COUNTER: PerCpuHashMap<u16, Counter> =
+    PerCpuHashMap::<u16, Counter>::with_max_entries(1024, 0);
 #[map]
-static mut v6_INGRESS_COUNTER: HashMap<u128, u64> = HashMap::<u128, u64>::with_max_entries(1024, 0);
+static BIFT_INGRESS_COUNTER: PerCpuHashMap<u32, Counter> =
+    PerCpuHashMap::<u32, Counter>::with_max_entries(256, 0);
+
+
+// Bit for BFER in indexed byte, aligned to the right end of the bitstring.
+// Single entry map instead of a global, so the bits can be changed at runtime.
+#[map]
+static LOCAL_BFER: Array<[u8; MAX_BITSTRING_LEN]> =
+    Array::<[u8; MAX_BITSTRING_LEN]>::with_max_entries(1, 0);
+
+// Local BIERin6 addresses, other IPv6 packets are not BIER for this BFR
+#[map]
+static BIERIN6_LOCAL: HashMap<u128, u8> = HashMap::<u128, u8>::with_max_entries(16, 0);
+
+pub const BS_OFFSET: usize = 12;
+
+// Local BFER bits, looked up once per packet. Byte i of a bitstring with len bytes is
+// matched against byte MAX_BITSTRING_LEN - len + i.
+#[inline(always)]
+pub fn local_bfer() -> Result<&'static [u8; MAX_BITSTRING_LEN], Reason> {
+    LOCAL_BFER.get(0).ok_or(Reason::MapLookupFailed)
+}
+
+// Ethertype restored after decapsulation, None if the payload is a complete Ethernet frame
+#[inline(always)]
+pub fn inner_ether_type(proto: u8) -> Result<Option<u16>, Reason> {
+    match proto {
+        BIER_PROTO_IPV4 => Ok(Some(EtherType::Ipv4 as u16)),
+        BIER_PROTO_IPV6 => Ok(Some(EtherType::Ipv6 as u16)),
+        BIER_PROTO_ETHERNET => Ok(None),
+        _ => Err(Reason::UnknownProto),
+    }
+}
+
+// MPLS BIER is only accepted for configured BIFT-ids and the BIER nibble,
+// other MPLS traffic is passed on unmodified
+#[inline(always)]
+pub fn is_mpls_bier(bift_id: u32, nibble: u8) -> bool {
+    if nibble != MPLS_BIER_NIBBLE {
+        return false;
+    }
+    match unsafe { BIFT_INFO.get(&bift_id) } {
+        Some(info) => info.mpls != 0,
+        None => false,
+    }
+}
+
+// Count a decapsulated packet, the destination is only known for IP payloads
+#[inline(always)]
+pub fn count_decap(bift_id: u32, bfir_id: u16, dst: InnerDst, bytes: u64) {
+    count_packet(&BIFT_INGRESS_COUNTER, &bift_id, bytes);
+    count_packet(&BFIR_INGRESS_COUNTER, &bfir_id, bytes);
+    match dst {
+        InnerDst::V4(addr) => count_packet(&v4_INGRESS_COUNTER, &addr, bytes),
+        InnerDst::V6(addr) => count_packet(&v6_INGRESS_COUNTER, &addr, bytes),
+        InnerDst::Unknown => {}
+    }
+}
+
+// Destination address of the packet after decapsulation
+pub enum InnerDst {
+    V4(u32),
+    V6(u128),
+    Unknown,
+}
+
+// Parse the destination of the decapsulated frame starting at offset 0
+#[inline(always)]
+fn inner_dst(ctx: &XdpContext) -> Result<InnerDst, Reason> {
+    let ethhdr: *const EthHdr = ptr_at(ctx, 0)?;
+    let ether_type = unsafe { (*ethhdr).ether_type };
+
+    if ether_type == EtherType::Ipv4 as u16 {
+        let dst: *const [u8; 4] = ptr_at(ctx, EthHdr::LEN + offset_of!(Ipv4Hdr, dst_addr))?;
+        Ok(InnerDst::V4(u32::from_be_bytes(unsafe { *dst })))
+    } else if ether_type == EtherType::Ipv6 as u16 {
+        let dst: *const [u8; 16] = ptr_at(ctx, EthHdr::LEN + offset_of!(Ipv6Hdr, dst_addr))?;
+        Ok(InnerDst::V6(u128::from_be_bytes(unsafe { *dst })))
+    } else {
+        Ok(InnerDst::Unknown)
+    }
+}
+
+// Offset of the BIER header in a BIERin6 packet, None if the IPv6 packet does not carry BIER
+// or is not sent to a local BIERin6 address
+#[inline(always)]
+fn bierin6_offset<P: Packet>(ctx: &P) -> Result<Option<usize>, Reason> {
+    let dst: [u8; 16] = ctx.read(EthHdr::LEN + offset_of!(Ipv6Hdr, dst_addr))?;
+    if unsafe { BIERIN6_LOCAL.get(&u128::from_be_bytes(dst)) }.is_none() {
+        return Ok(None);
+    }
+
+    let next_hdr: u8 = ctx.read(EthHdr::LEN + IPV6_NEXT_HDR_OFFSET)?;
+    if next_hdr != NEXT_HDR_DEST_OPTS {
+        return Ok(None);
+    }
+
+    let opt_type: u8 = ctx.read(EthHdr::LEN + IPV6_HDR_LEN + DEST_OPTS_TYPE_OFFSET)?;
+    if opt_type != BIERIN6_OPTION_TYPE {
+        return Ok(None);
+    }
+
+    Ok(Some(EthHdr::LEN + BIERIN6_BIER_OFFSET))
+}
 
+// Policy for packets without matching bit, BIFT-ids without settings drop them
+pub enum NoMatch {
+    Drop,
+    Pass,
+    Redirect(u32),
+}
 
-#[no_mangle]
-// Bit for BFER in indexed byte
-static mut LOCAL_BFER: [u8; 32] = [0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];
+#[inline(always)]
+pub fn no_match_policy(bift_id: u32) -> NoMatch {
+    match unsafe { BIFT_INFO.get(&bift_id) } {
+        Some(info) if info.no_match == NO_MATCH_PASS => NoMatch::Pass,
+        Some(info) if info.no_match == NO_MATCH_REDIRECT => NoMatch::Redirect(info.redirect_ifindex),
+        _ => NoMatch::Drop,
+    }
+}
 
-const BS_OFFSET: usize = 12;
+// What happens to a received BIER packet, decided the same way by the XDP and TC ingress programs
+pub enum Verdict {
+    // Not BIER, passed on without being counted
+    Pass,
+    // BIER-TE or bits of other BFRs, replicated by the TC forwarding program
+    Forward,
+    // No matching bit, redirected by the policy of the BIFT-id
+    Redirect(u32),
+    Decap(Decap),
+}
 
+// Local bit matched, everything between Ethernet header and payload is removed
+pub struct Decap {
+    pub bift_id: u32,
+    pub bfir_id: u16,
+    pub proto: u8,
+    pub bier_offset: usize,
+    pub outer_len: usize,
+}
+
+// Parse the BIER header and match the bitstring against the local BFER bits.
+// BIER packets that are dropped or passed unmodified are returned with their reason.
 #[inline(always)]
-pub fn process_ingress(ctx: XdpContext) -> Result<u32, ()> {
-    let ethhdr: *mut EthHdr = ptr_mut_at(&ctx, 0)?;
-    let ethertype = unsafe { (*ethhdr).ether_type as u16 };
+pub fn classify<P: Packet>(ctx: &mut P) -> Result<Verdict, Reason> {
+    let ethertype: u16 = ctx.read(offset_of!(EthHdr, ether_type))?;
+
+    // Continue only if BIER packet, BIERin6 carries the BIER header behind the IPv6 header
+    let bier_offset = if ethertype == BIER_ETHERTYPE || ethertype == BIER_MPLS_ETHERTYPE {
+        EthHdr::LEN
+    } else if ethertype == EtherType::Ipv6 as u16 {
+        match bierin6_offset(ctx)? {
+            Some(offset) => offset,
+            None => return Ok(Verdict::Pass),
+        }
+    } else {
+        return Ok(Verdict::Pass);
+    };
+    let bierin6 = bier_offset != EthHdr::LEN;
+
+    let word0 = Word0::from_bytes(ctx.read(bier_offset + WORD0_OFFSET)?);
+    let bift_id = word0.bift_id();
+    let word1 = Word1::from_bytes(ctx.read(bier_offset + WORD1_OFFSET)?);
 
-    // Continue only if BIER packet
-    if ethertype != BIER_ETHERTYPE {
-        info!(&ctx, "Passed non-BIER packet: {}", ethertype);
-        return Ok(xdp_action::XDP_PASS);
+    if ethertype == BIER_MPLS_ETHERTYPE && !is_mpls_bier(bift_id, word1.nibble()) {
+        return Ok(Verdict::Pass);
     }
 
-    // Packet is BIER, check BFER bit to remove BIER
-    //let bier_hdr: *const BierHdr = ptr_at(&ctx, EthHdr::LEN)?;
+    // TTL is checked only for copies leaving this BFR, local bits are decapsulated regardless
+    let word2 = Word2::from_bytes(ctx.read(bier_offset + WORD2_OFFSET)?);
 
+    // Size of BIER header is given by the BSL
+    let bsl = word1.bsl();
+    let bs_len = bitstring_len(bsl);
+    let hdr_len = header_len(bsl);
+    if bs_len == 0 || (bierin6 && bsl > BIERIN6_MAX_BSL) {
+        error!(ctx, "Invalid BSL {}, dropping packet!", bsl);
+        return Err(Reason::InvalidBsl);
+    }
+
+    // BIER-TE bits are adjacencies instead of BFERs, handled by the TC forwarding program
+    if let Some(info) = unsafe { BIFT_INFO.get(&bift_id) } {
+        if info.mode == BIFT_MODE_TE && !bierin6 {
+            info!(ctx, "Passed BIER-TE packet to forwarding");
+            return Ok(Verdict::Forward);
+        }
+    }
 
     // Iterate over bytes in BIER bitstring
+    let local_bfer = local_bfer()?;
+    let local_offset = MAX_BITSTRING_LEN - bs_len;
     let mut bit_match: bool = false;
-    for i in 0..32 {
-        let bs_byte: *const u8 = ptr_mut_at(&ctx, EthHdr::LEN  + BS_OFFSET + i)?;
-        if unsafe {
-            (*bs_byte) & LOCAL_BFER[i] > 0
-        } {
-            bit_match = true;
+    let mut foreign_bits: bool = false;
+    for i in 0..MAX_BITSTRING_LEN {
+        if i >= bs_len {
             break;
         }
+        let bs_byte: u8 = ctx.read(bier_offset + BS_OFFSET + i)?;
+        let local = match local_bfer.get(local_offset + i) {
+            Some(local) => *local,
+            None => 0,
+        };
+        if bs_byte & local > 0 {
+            bit_match = true;
+        }
+        if bs_byte & !local > 0 {
+            foreign_bits = true;
+        }
+    }
+
+    // XDP cannot clone packets, replication is done by the TC forwarding program.
+    // BIERin6 packets are only decapsulated locally.
+    if foreign_bits && !bierin6 && unsafe { BFR_FORWARDING } != 0 {
+        info!(ctx, "Passed BIER packet to forwarding");
+        return Ok(Verdict::Forward);
     }
 
     if !bit_match {
-        error!(&ctx,"No BFER bit match, dropping packet!"); 
-        return  Err(());
+        return match no_match_policy(bift_id) {
+            NoMatch::Pass => {
+                info!(ctx, "No BFER bit match, passing BIER packet");
+                Err(Reason::NoBitMatchPassed)
+            }
+            // Redirected packets leave this BFR like forwarded copies
+            NoMatch::Redirect(_) if !decrement_ttl(ctx, bier_offset, word0)? => Err(Reason::TtlExpired),
+            NoMatch::Redirect(ifindex) => {
+                info!(ctx, "No BFER bit match, redirecting BIER packet to {}", ifindex);
+                count_reason(Reason::NoBitMatchRedirected);
+                Ok(Verdict::Redirect(ifindex))
+            }
+            NoMatch::Drop => {
+                error!(ctx, "No BFER bit match, dropping packet!");
+                Err(Reason::NoBitMatch)
+            }
+        };
     }
 
-    // Get new start location of ethernet header (shifted right by size of BIER header)
-    let new_ethhdr: *mut EthHdr = ptr_mut_at(&ctx, BIER_HEADER_SIZE)?;
+    Ok(Verdict::Decap(Decap {
+        bift_id,
+        bfir_id: word2.bfir_id(),
+        proto: word2.proto(),
+        bier_offset,
+        // Everything between Ethernet header and payload
+        outer_len: bier_offset - EthHdr::LEN + hdr_len,
+    }))
+}
+
+#[inline(always)]
+pub fn process_ingress(mut ctx: XdpContext) -> Result<u32, Reason> {
+    match classify(&mut ctx)? {
+        Verdict::Pass | Verdict::Forward => Ok(xdp_action::XDP_PASS),
+        Verdict::Redirect(ifindex) => Ok(unsafe { bpf_redirect(ifindex, 0) } as u32),
+        Verdict::Decap(decap) => xdp_decap(&ctx, decap),
+    }
+}
+
+// Decapsulation at TC ingress for interfaces without XDP, forwarding happens in the same program
+#[inline(always)]
+pub fn process_ingress_tc(mut ctx: TcContext) -> Result<i32, Reason> {
+    match classify(&mut ctx)? {
+        Verdict::Pass => Ok(TC_ACT_OK),
+        Verdict::Forward => process_forward(ctx),
+        Verdict::Redirect(ifindex) => Ok(unsafe { bpf_redirect(ifindex, 0) } as i32),
+        Verdict::Decap(decap) => decapsulate(&mut ctx, decap.bier_offset, decap.outer_len),
+    }
+}
+
+#[inline(always)]
+fn xdp_decap(ctx: &XdpContext, decap: Decap) -> Result<u32, Reason> {
+    let Decap { bift_id, bfir_id, proto, outer_len, .. } = decap;
+
+    // Next protocol decides what is restored after the BIER header
+    let ether_type = match inner_ether_type(proto) {
+        Ok(Some(ether_type)) => ether_type,
+        Ok(None) => {
+            // Payload is a complete Ethernet frame, drop outer Ethernet and BIER header
+            if unsafe { bpf_xdp_adjust_head(ctx.ctx, (EthHdr::LEN + outer_len) as i32) } != 0 {
+                error!(ctx, "Failed to adjust XDP head! Dropping.");
+                return Err(Reason::AdjustHeadFailed);
+            }
+
+            let dst = inner_dst(ctx).unwrap_or(InnerDst::Unknown);
+            count_decap(bift_id, bfir_id, dst, xdp_len(ctx));
+
+            info!(ctx, "Successfully decapsulated BIER packet!");
+
+            return Ok(xdp_action::XDP_PASS);
+        }
+        Err(reason) => {
+            error!(ctx, "Unknown BIER next protocol {}, dropping packet!", proto);
+            return Err(reason);
+        }
+    };
+
+    let ethhdr: *mut EthHdr = ptr_mut_at(ctx, 0)?;
+
+    // Get new start location of ethernet header (shifted right by size of removed headers)
+    let new_ethhdr: *mut EthHdr = ptr_mut_at(ctx, outer_len)?;
 
     // Copy old eth header to new location in packet
     unsafe {
         memcpy(new_ethhdr as *mut _, ethhdr as *mut _, EthHdr::LEN);
     }
 
-    // Adjust ethertype back to IPv4
-    // TODO: needs mapping based on BIER Next header fields!
+    // Restore ethertype of the encapsulated packet
     unsafe {
-        (*new_ethhdr).ether_type = EtherType::Ipv4;
+        (*new_ethhdr).ether_type = ether_type;
     }
 
     // Move head back to start of new eth header
     // Delta is size of removed header
-    if unsafe { bpf_xdp_adjust_head(ctx.ctx, BIER_HEADER_SIZE as i32) } != 0 {
-        error!(&ctx, "Failed to adjust XDP head! Dropping.");
-        return Err(()); // Causes packet drop in calling func
+    if unsafe { bpf_xdp_adjust_head(ctx.ctx, outer_len as i32) } != 0 {
+        error!(ctx, "Failed to adjust XDP head! Dropping.");
+        return Err(Reason::AdjustHeadFailed); // Causes packet drop in calling func
     }
 
-    info!(&ctx, "Successfully decapsulated BIER packet!");
+    let dst = inner_dst(ctx).unwrap_or(InnerDst::Unknown);
+    count_decap(bift_id, bfir_id, dst, xdp_len(ctx));
+
+    info!(ctx, "Successfully decapsulated BIER packet!");
 
     Ok(xdp_action::XDP_PASS)
 }
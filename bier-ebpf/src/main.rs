@@ -4,22 +4,69 @@
 pub mod util;
 pub mod ingress;
 pub mod egress;
+pub mod forward;
+pub mod reason;
+pub mod ttl;
+pub mod icmp;
 
 const NUM_CPUS: usize = 16;
 
 use aya_ebpf::{
-    bindings::{xdp_action, TC_ACT_OK},
+    bindings::{xdp_action, TC_ACT_OK, TC_ACT_SHOT},
     macros::{classifier, xdp},
     programs::{TcContext, XdpContext},
 };
 
-use crate::{egress::process_egress, ingress::process_ingress};
+use crate::{
+    egress::process_egress,
+    forward::process_forward,
+    ingress::{process_ingress, process_ingress_tc},
+    reason::count_reason,
+};
 
 #[classifier]
 pub fn bier_egress(ctx: TcContext) -> i32 {
     match process_egress(ctx) {
         Ok(ret) => ret,
-        Err(_) => TC_ACT_OK,
+        Err(reason) => {
+            count_reason(reason);
+            if reason.is_drop() {
+                TC_ACT_SHOT
+            } else {
+                TC_ACT_OK
+            }
+        }
+    }
+}
+
+#[classifier]
+pub fn bier_forward(ctx: TcContext) -> i32 {
+    match process_forward(ctx) {
+        Ok(ret) => ret,
+        Err(reason) => {
+            count_reason(reason);
+            if reason.is_drop() {
+                TC_ACT_SHOT
+            } else {
+                TC_ACT_OK
+            }
+        }
+    }
+}
+
+// Replaces bier_ingress and bier_forward on interfaces without XDP
+#[classifier]
+pub fn bier_ingress_tc(ctx: TcContext) -> i32 {
+    match process_ingress_tc(ctx) {
+        Ok(ret) => ret,
+        Err(reason) => {
+            count_reason(reason);
+            if reason.is_drop() {
+                TC_ACT_SHOT
+            } else {
+                TC_ACT_OK
+            }
+        }
     }
 }
 
@@ -27,7 +74,14 @@ pub fn bier_egress(ctx: TcContext) -> i32 {
 pub fn bier_ingress(ctx: XdpContext) -> u32 {
     match process_ingress(ctx) {
         Ok(ret) => ret,
-        Err(_) => xdp_action::XDP_ABORTED,
+        Err(reason) => {
+            count_reason(reason);
+            if reason.is_drop() {
+                xdp_action::XDP_DROP
+            } else {
+                xdp_action::XDP_PASS
+            }
+        }
     }
 }
 
@@ -0,0 +1,14 @@
+use aya_ebpf::{macros::map, maps::PerCpuArray};
+
+use bier_common::reason::{Reason, NUM_REASONS};
+
+// Packets dropped or passed unmodified, indexed by reason
+#[map]
+static REASON_COUNTER: PerCpuArray<u64> = PerCpuArray::<u64>::with_max_entries(NUM_REASONS, 0);
+
+#[inline(always)]
+pub fn count_reason(reason: Reason) {
+    if let Some(counter) = REASON_COUNTER.get_ptr_mut(reason as u32) {
+        unsafe { *counter += 1 };
+    }
+}
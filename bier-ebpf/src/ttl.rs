@@ -0,0 +1,54 @@
+use aya_ebpf::{
+    macros::map,
+    maps::{PerCpuHashMap, RingBuf},
+};
+use aya_log_ebpf::warn;
+
+use bier_common::{
+    bier::{Word0, Word2, WORD0_OFFSET, WORD2_OFFSET},
+    counter::Counter,
+    reason::Reason,
+    ttl::TtlExpiry,
+};
+
+use crate::util::{count_packet, Packet};
+
+// Packets with expired TTL by BIFT-id
+#[map]
+static TTL_EXPIRED_COUNTER: PerCpuHashMap<u32, Counter> =
+    PerCpuHashMap::<u32, Counter>::with_max_entries(256, 0);
+
+// Notifications about expired packets, only used if TTL_NOTIFY is set
+#[map]
+static TTL_EVENTS: RingBuf = RingBuf::with_byte_size(4096, 0);
+
+#[no_mangle]
+// Set if userspace wants a notification for every expired packet
+pub static mut TTL_NOTIFY: u8 = 0;
+
+// Count packet with expired TTL and notify userspace
+#[inline(always)]
+pub fn ttl_expired(bift_id: u32, bfir_id: u16, bytes: u64) {
+    count_packet(&TTL_EXPIRED_COUNTER, &bift_id, bytes);
+
+    // Volatile, the value is set by userspace at load time and must not be folded to 0
+    if unsafe { core::ptr::read_volatile(core::ptr::addr_of!(TTL_NOTIFY)) } != 0 {
+        // Events are dropped if userspace does not keep up
+        let _ = TTL_EVENTS.output(&TtlExpiry::new(bift_id, bfir_id), 0);
+    }
+}
+
+// Decrement the TTL of all copies, false if the TTL expires and no copies may be forwarded
+#[inline(always)]
+pub fn decrement_ttl<P: Packet>(ctx: &mut P, bier_offset: usize, word0: Word0) -> Result<bool, Reason> {
+    let ttl = word0.ttl();
+    if ttl <= 1 {
+        let bfir_id = Word2::from_bytes(ctx.read(bier_offset + WORD2_OFFSET)?).bfir_id();
+        warn!(ctx, "TTL expired for BIFT-id {} from BFIR-id {}, not forwarding!", word0.bift_id(), bfir_id);
+        ttl_expired(word0.bift_id(), bfir_id, ctx.frame_len());
+        return Ok(false);
+    }
+
+    ctx.write(bier_offset + WORD0_OFFSET, &word0.with_ttl(ttl - 1).into_bytes())?;
+    Ok(true)
+}
@@ -1,6 +1,9 @@
-use core::mem;
+use core::{mem, ptr};
 
+use aya_ebpf::maps::PerCpuHashMap;
 use aya_ebpf::programs::{TcContext, XdpContext};
+use aya_ebpf::EbpfContext;
+use bier_common::{counter::Counter, reason::Reason};
 
 // Better EthHdt that does not use an enum!
 #[repr(C, packed)]
@@ -17,55 +20,141 @@ impl EthHdr {
 }
 
 #[inline(always)] // 
-pub fn ptr_at<T>(ctx: &XdpContext, offset: usize) -> Result<*const T, ()> {
+pub fn ptr_at<T>(ctx: &XdpContext, offset: usize) -> Result<*const T, Reason> {
     let start = ctx.data();
     let end = ctx.data_end();
     let len = mem::size_of::<T>();
 
     if start + offset + len > end {
-        return Err(());
+        return Err(Reason::OutOfBounds);
     }
 
     Ok((start + offset) as *const T)
 }
 
 #[inline(always)] // 
-pub fn tc_ptr_at<T>(ctx: &TcContext, offset: usize) -> Result<*const T, ()> {
+pub fn tc_ptr_at<T>(ctx: &TcContext, offset: usize) -> Result<*const T, Reason> {
     let start = ctx.data();
     let end = ctx.data_end();
     let len = mem::size_of::<T>();
 
     if start + offset + len > end {
-        return Err(());
+        return Err(Reason::OutOfBounds);
     }
 
     Ok((start + offset) as *const T)
 }
 
 #[inline(always)]
-pub fn ptr_mut_at<T>(ctx: &XdpContext, offset: usize) -> Result<*mut T, ()> {
+pub fn ptr_mut_at<T>(ctx: &XdpContext, offset: usize) -> Result<*mut T, Reason> {
     let start = ctx.data();
     let end = ctx.data_end();
     let len = mem::size_of::<T>();
 
     if start + offset + len > end {
-        return Err(());
+        return Err(Reason::OutOfBounds);
     }
 
     Ok((start + offset) as *mut T)
 }
 
 #[inline(always)]
-pub fn tc_ptr_mut_at<T>(ctx: &TcContext, offset: usize) -> Result<*mut T, ()> {
+pub fn tc_ptr_mut_at<T>(ctx: &TcContext, offset: usize) -> Result<*mut T, Reason> {
     let start = ctx.data();
     let end = ctx.data_end();
     let len = mem::size_of::<T>();
 
     if start + offset + len > end {
-        return Err(());
+        return Err(Reason::OutOfBounds);
     }
 
     Ok((start + offset) as *mut T)
 }
 
 
+
+// Count a packet for a key on the current CPU, counters of keys not known in advance are created on first use
+#[inline(always)]
+pub fn count_packet<K>(map: &PerCpuHashMap<K, Counter>, key: &K, bytes: u64) {
+    match map.get_ptr_mut(key) {
+        Some(counter) => unsafe { (*counter).add_packet(bytes) },
+        None => {
+            let mut counter = Counter::default();
+            counter.add_packet(bytes);
+            let _ = map.insert(key, &counter, 0);
+        }
+    }
+}
+
+// Length of the frame in an XDP context
+#[inline(always)]
+pub fn xdp_len(ctx: &XdpContext) -> u64 {
+    (ctx.data_end() - ctx.data()) as u64
+}
+
+// Access to received frames, so XDP and TC ingress share the parsing
+pub trait Packet: EbpfContext {
+    fn read<T: Copy>(&self, offset: usize) -> Result<T, Reason>;
+    fn write<T: Copy>(&mut self, offset: usize, value: &T) -> Result<(), Reason>;
+    fn frame_len(&self) -> u64;
+}
+
+impl Packet for XdpContext {
+    #[inline(always)]
+    fn read<T: Copy>(&self, offset: usize) -> Result<T, Reason> {
+        let ptr: *const T = ptr_at(self, offset)?;
+        Ok(unsafe { ptr::read_unaligned(ptr) })
+    }
+
+    #[inline(always)]
+    fn write<T: Copy>(&mut self, offset: usize, value: &T) -> Result<(), Reason> {
+        let ptr: *mut T = ptr_mut_at(self, offset)?;
+        unsafe { ptr::write_unaligned(ptr, *value) };
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn frame_len(&self) -> u64 {
+        xdp_len(self)
+    }
+}
+
+impl Packet for TcContext {
+    #[inline(always)]
+    fn read<T: Copy>(&self, offset: usize) -> Result<T, Reason> {
+        self.load(offset).map_err(|_| Reason::OutOfBounds)
+    }
+
+    #[inline(always)]
+    fn write<T: Copy>(&mut self, offset: usize, value: &T) -> Result<(), Reason> {
+        self.store(offset, value, 0).map_err(|_| Reason::LoadStoreFailed)
+    }
+
+    #[inline(always)]
+    fn frame_len(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+// Ones' complement sum of 16 bit words (RFC 1071), odd lengths are padded with zero
+#[inline(always)]
+pub fn csum_add<const N: usize>(sum: u32, data: &[u8; N]) -> u32 {
+    let mut sum = sum;
+    let mut i = 0;
+    while i + 1 < N {
+        sum += u16::from_be_bytes([data[i], data[i + 1]]) as u32;
+        i += 2;
+    }
+    if N % 2 == 1 {
+        sum += (data[N - 1] as u32) << 8;
+    }
+    sum
+}
+
+// Fold the carries of a sum of at most 2^16 words and complement it
+#[inline(always)]
+pub fn csum_fold(sum: u32) -> u16 {
+    let sum = (sum & 0xffff) + (sum >> 16);
+    let sum = (sum & 0xffff) + (sum >> 16);
+    !(sum as u16)
+}
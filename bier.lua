@@ -24,7 +24,7 @@ f.dscp    = ProtoField.uint32("bier.dscp", "DSCP", base.DEC, nil,         0x0FC0
 f.proto   = ProtoField.uint32("bier.proto", "Proto", base.HEX, nil,       0x003F0000)
 f.bfir_id = ProtoField.uint32("bier.bfir_id", "BFIR-ID", base.DEC, nil,   0x0000FFFF)
 
--- Word 4 - 7
+-- Word 4 - n
 f.bitstring = ProtoField.bytes("bier.bitstring", "BitString", base.BIN)
 
 local bier_proto_dissector_table = DissectorTable.new("bier", "BIER Next Protocold", ftypes.UINT8, base.DEC)
@@ -58,16 +58,21 @@ function bier_proto.dissector(buffer, pinfo, tree)
     subtree:add(f.proto, buffer(8, 4))
     subtree:add(f.bfir_id, buffer(8, 4))
 
-    -- Word 4: Bitstring
-    local bitstring_tree = subtree:add(buffer(12, 32), "-----------------------------------------")
-    subtree:add(f.bitstring, buffer(12, 32))
+    -- Word 4: Bitstring, length given by BSL (1: 64 bits ... 7: 4096 bits)
+    local bsl = bit.rshift(bit.band(buffer(4, 4):uint(), 0x00F00000), 20)
+    if bsl < 1 or bsl > 7 then return 0 end
+    local bitstring_len = bit.lshift(8, bsl - 1)
+    if buffer:len() < 12 + bitstring_len then return 0 end
+
+    local bitstring_tree = subtree:add(buffer(12, bitstring_len), "-----------------------------------------")
+    subtree:add(f.bitstring, buffer(12, bitstring_len))
 
     -- Next header
     local word3 = buffer(8, 4):uint()
     local proto_val = bit.rshift(bit.band(word3, 0x003F0000), 16)
 
     -- Pass to next dissector using custom table
-    local payload_offset = 44
+    local payload_offset = 12 + bitstring_len
     if buffer:len() > payload_offset then
         local payload = buffer(payload_offset):tvb()
         bier_proto_dissector_table:try(proto_val, payload, pinfo, tree)
@@ -0,0 +1,93 @@
+// Programs attached to the interface, read from the kernel on every query so that programs
+// detached behind the back of the daemon show up as such. TC programs are attached as TCX
+// links since kernel 6.6 and as netlink filters before.
+
+use aya::programs::{loaded_programs, SchedClassifier, TcAttachType};
+
+use crate::{
+    netlink::{attrs, dump},
+    xdp,
+};
+
+const TCMSG_LEN: usize = 20;
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+const TCA_BPF_NAME: u16 = 7;
+// clsact parents, TC_H_MAKE(TC_H_CLSACT, TC_H_MIN_INGRESS / TC_H_MIN_EGRESS)
+const PARENT_INGRESS: u32 = 0xffff_fff2;
+const PARENT_EGRESS: u32 = 0xffff_fff3;
+
+pub struct Attached {
+    iface: String,
+    // Program and attach mode
+    programs: Vec<(String, String)>,
+}
+
+impl Attached {
+    pub fn new(iface: &str, programs: Vec<(String, String)>) -> Attached {
+        Attached {
+            iface: iface.to_string(),
+            programs,
+        }
+    }
+
+    // Programs with their attach mode and whether they are still attached
+    pub fn state(&self) -> Vec<(String, String, bool)> {
+        let if_index = xdp::if_index(&self.iface).ok();
+        self.programs
+            .iter()
+            .map(|(program, mode)| {
+                let attached = if_index.is_some_and(|if_index| match program.as_str() {
+                    "bier_ingress" => xdp_attached(if_index, program),
+                    "bier_egress" => tc_attached(&self.iface, if_index, TcAttachType::Egress, program),
+                    _ => tc_attached(&self.iface, if_index, TcAttachType::Ingress, program),
+                });
+                (program.clone(), mode.clone(), attached)
+            })
+            .collect()
+    }
+}
+
+fn xdp_attached(if_index: u32, program: &str) -> bool {
+    let Ok(Some(id)) = xdp::attached_id(if_index) else {
+        return false;
+    };
+    loaded_programs()
+        .filter_map(Result::ok)
+        .any(|info| info.id() == id && info.name_as_str() == Some(program))
+}
+
+fn tc_attached(iface: &str, if_index: u32, attach_type: TcAttachType, program: &str) -> bool {
+    // TCX links, the query fails on kernels without TCX
+    if let Ok((_, infos)) = SchedClassifier::query_tcx(iface, attach_type) {
+        if infos.iter().any(|info| info.name_as_str() == Some(program)) {
+            return true;
+        }
+    }
+
+    // Netlink filters of the clsact qdisc
+    let parent = match attach_type {
+        TcAttachType::Egress => PARENT_EGRESS,
+        _ => PARENT_INGRESS,
+    };
+    // tcm_family, padding, tcm_ifindex, tcm_handle, tcm_parent, tcm_info
+    let mut tcmsg = Vec::with_capacity(TCMSG_LEN);
+    tcmsg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    tcmsg.extend_from_slice(&(if_index as i32).to_ne_bytes());
+    tcmsg.extend_from_slice(&0u32.to_ne_bytes());
+    tcmsg.extend_from_slice(&parent.to_ne_bytes());
+    tcmsg.extend_from_slice(&0u32.to_ne_bytes());
+
+    let Ok(filters) = dump(libc::RTM_GETTFILTER, &tcmsg) else {
+        return false;
+    };
+    filters.iter().any(|filter| {
+        let filter_attrs = attrs(filter.get(TCMSG_LEN..).unwrap_or_default());
+        let bpf = filter_attrs.iter().any(|(kind, payload)| *kind == TCA_KIND && *payload == b"bpf\0");
+        bpf && filter_attrs
+            .iter()
+            .filter(|(kind, _)| *kind == TCA_OPTIONS)
+            .flat_map(|(_, options)| attrs(options))
+            .any(|(kind, name)| kind == TCA_BPF_NAME && name.split(|b| *b == 0).next() == Some(program.as_bytes()))
+    })
+}
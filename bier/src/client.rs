@@ -0,0 +1,260 @@
+// Commands that talk to a running daemon over the control socket, see control.rs
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{IpAddr, Ipv6Addr},
+    os::unix::net::UnixStream,
+};
+
+use anyhow::Context;
+use bier_common::bier::{bitstring_len, BIER_PROTO_IPV4, BIER_PROTO_IPV6, MPLS_BIER_NIBBLE};
+use clap::{Args, ValueEnum};
+use serde_json::Value;
+
+use crate::{
+    config::{bfr_ids, bitstring_of, BierMapping, EntropyMode, Encap, MtuPolicy, SerdeBierHdr},
+    control::{Counters, Request, Response},
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Show {
+    /// Mappings of all groups
+    Groups,
+    /// Egress, receive, TTL expiry and reason counters
+    Counters,
+    /// Local BFR-ids
+    Local,
+}
+
+// Mapping of a group, fields default to those of `SerdeBierHdr::new`
+#[derive(Debug, Args)]
+pub struct AddOpt {
+    /// IPv4 or IPv6 multicast group
+    group: IpAddr,
+    /// BIFT-id of the BIER header
+    #[clap(long)]
+    bift_id: u32,
+    /// BitString length, 1 (64 bits) to 7 (4096 bits)
+    #[clap(long, default_value_t = 1)]
+    bsl: u8,
+    /// BFR-ids of the BFERs, comma separated
+    #[clap(long, value_delimiter = ',')]
+    bfr_ids: Vec<u32>,
+    /// BFR-id of this BFIR
+    #[clap(long, default_value_t = 0)]
+    bfir_id: u16,
+    /// TTL of encapsulated packets
+    #[clap(long, default_value_t = 64)]
+    ttl: u8,
+    /// Traffic class
+    #[clap(long, default_value_t = 0)]
+    tc: u8,
+    /// DSCP of the BIER header
+    #[clap(long, default_value_t = 0)]
+    dscp: u8,
+    /// Entropy of the static entropy mode
+    #[clap(long, default_value_t = 0)]
+    entropy: u32,
+    /// Next protocol, IPv4 or IPv6 by the group address if not given
+    #[clap(long)]
+    proto: Option<u8>,
+    /// Encapsulation of the BIER header
+    #[clap(long, value_enum, default_value = "ethernet")]
+    encap: Encap,
+    /// Outer IPv6 source address for BIERin6
+    #[clap(long)]
+    src: Option<Ipv6Addr>,
+    /// Outer IPv6 destination address for BIERin6
+    #[clap(long)]
+    dst: Option<Ipv6Addr>,
+    /// Packets that exceed the MTU once encapsulated
+    #[clap(long, value_enum, default_value = "drop")]
+    mtu_policy: MtuPolicy,
+    /// Entropy written to the header, static or computed per packet
+    #[clap(long, value_enum, default_value = "static")]
+    entropy_mode: EntropyMode,
+}
+
+impl AddOpt {
+    fn header(&self) -> anyhow::Result<SerdeBierHdr> {
+        let len = bitstring_len(self.bsl);
+        if len == 0 {
+            return Err(anyhow::Error::msg(format!("Invalid BSL {}", self.bsl)));
+        }
+        let mpls = self.encap == Encap::Mpls;
+        let proto = self.proto.unwrap_or(match self.group {
+            IpAddr::V4(_) => BIER_PROTO_IPV4,
+            IpAddr::V6(_) => BIER_PROTO_IPV6,
+        });
+
+        Ok(SerdeBierHdr {
+            tc: self.tc,
+            s: mpls,
+            ttl: self.ttl,
+            nibble: if mpls { MPLS_BIER_NIBBLE } else { 0 },
+            entropy: self.entropy,
+            dscp: self.dscp,
+            bfir_id: self.bfir_id,
+            bitstring: bitstring_of(&self.bfr_ids, len)?,
+            encap: self.encap,
+            src: self.src,
+            dst: self.dst,
+            mtu_policy: self.mtu_policy,
+            entropy_mode: self.entropy_mode,
+            ..SerdeBierHdr::new(self.bift_id, self.bsl, proto)
+        })
+    }
+}
+
+// Send a single request, the data of the response is returned if it succeeded
+fn request(socket: &str, request: &Request) -> anyhow::Result<Option<Value>> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed connecting to the daemon at {socket}, is `bier run` running?"))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Response = serde_json::from_str(&line).context("Invalid response from the daemon")?;
+    if !response.ok {
+        return Err(anyhow::Error::msg(response.error.unwrap_or_default()));
+    }
+    Ok(response.data)
+}
+
+fn format_ids(ids: &[u32]) -> String {
+    if ids.is_empty() {
+        return "-".to_string();
+    }
+    ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn print_groups(data: Value) -> anyhow::Result<()> {
+    let mappings: BierMapping = serde_json::from_value(data)?;
+    let mut groups: Vec<_> = mappings.iter().collect();
+    groups.sort_by_key(|(group, _)| **group);
+
+    println!("{:<40} {:>8} {:>4} {:>8} {:<9} BFR-ids", "Group", "BIFT-id", "BSL", "BFIR-id", "Encap");
+    for (group, hdr) in groups {
+        println!(
+            "{:<40} {:>8} {:>4} {:>8} {:<9} {}",
+            group.to_string(),
+            hdr.bift_id,
+            hdr.bsl,
+            hdr.bfir_id,
+            format!("{:?}", hdr.encap),
+            format_ids(&bfr_ids(&hdr.bitstring))
+        );
+    }
+    Ok(())
+}
+
+fn print_counters(data: Value) -> anyhow::Result<()> {
+    let counters: Counters = serde_json::from_value(data)?;
+
+    for (title, rows) in [
+        ("Egress", &counters.egress),
+        ("Receive", &counters.rx),
+        ("TTL expired, BIFT-id", &counters.ttl_expired),
+    ] {
+        println!("{title}");
+        for row in rows {
+            println!("  {:<40} {:>12} packets {:>14} bytes", row.key, row.packets, row.bytes);
+        }
+    }
+    println!("Reasons");
+    for row in counters.reasons.iter().filter(|row| row.packets > 0) {
+        let verdict = if row.drop { "drop" } else { "pass" };
+        println!("  {:<40} {:>12} packets ({verdict})", row.reason, row.packets);
+    }
+    Ok(())
+}
+
+fn print_local(data: Value) -> anyhow::Result<()> {
+    let local: Vec<u8> = serde_json::from_value(data["local"].clone())?;
+    println!("{} bits, BFR-ids {}", local.len() * 8, format_ids(&bfr_ids(&local)));
+    Ok(())
+}
+
+fn print(data: Option<Value>, json: bool, human: fn(Value) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    match (data, json) {
+        (Some(data), true) => println!("{}", serde_json::to_string_pretty(&data)?),
+        (Some(data), false) => human(data)?,
+        (None, true) => println!("{{\"ok\":true}}"),
+        (None, false) => (),
+    }
+    Ok(())
+}
+
+pub fn show(socket: &str, what: Show, json: bool) -> anyhow::Result<()> {
+    match what {
+        Show::Groups => print(request(socket, &Request::List)?, json, print_groups),
+        Show::Counters => print(request(socket, &Request::Counters)?, json, print_counters),
+        Show::Local => print(request(socket, &Request::Local)?, json, print_local),
+    }
+}
+
+pub fn add(socket: &str, opt: &AddOpt, json: bool) -> anyhow::Result<()> {
+    let header = opt.header()?;
+    let data = request(socket, &Request::Add { group: opt.group, header })?;
+    print(data, json, |_| Ok(()))
+}
+
+pub fn del(socket: &str, group: IpAddr, json: bool) -> anyhow::Result<()> {
+    let data = request(socket, &Request::Del { group })?;
+    print(data, json, |_| Ok(()))
+}
+
+// Local bits are sized to fit the highest BFR-id
+pub fn set_local(socket: &str, ids: &[u32], json: bool) -> anyhow::Result<()> {
+    let len = (ids.iter().max().copied().unwrap_or(1) as usize).div_ceil(8);
+    let local = bitstring_of(ids, len)?;
+    let data = request(socket, &Request::SetLocal { local })?;
+    print(data, json, |_| Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        add: AddOpt,
+    }
+
+    fn header(args: &[&str]) -> anyhow::Result<SerdeBierHdr> {
+        let cli = Cli::try_parse_from(["bier"].iter().chain(args))?;
+        cli.add.header()
+    }
+
+    #[test]
+    fn test_add_header() {
+        let hdr = header(&["239.1.1.1", "--bift-id", "100", "--bfr-ids", "1,9", "--bfir-id", "3"]).unwrap();
+        assert_eq!(hdr.bift_id, 100);
+        assert_eq!(hdr.bfir_id, 3);
+        assert_eq!(hdr.ttl, 64);
+        assert_eq!(hdr.proto, BIER_PROTO_IPV4);
+        assert_eq!(hdr.bitstring, vec![0, 0, 0, 0, 0, 0, 1, 1]);
+        assert!(hdr.to_bier_hdr().is_ok());
+
+        // Next protocol follows the group, MPLS sets S and the nibble
+        let hdr = header(&["ff3e::1", "--bift-id", "1", "--bsl", "2", "--encap", "mpls"]).unwrap();
+        assert_eq!(hdr.proto, BIER_PROTO_IPV6);
+        assert_eq!(hdr.bitstring.len(), 16);
+        assert!(hdr.s);
+        assert_eq!(hdr.nibble, MPLS_BIER_NIBBLE);
+    }
+
+    #[test]
+    fn test_add_header_invalid() {
+        // BSL without a BitString length
+        assert!(header(&["239.1.1.1", "--bift-id", "1", "--bsl", "0"]).is_err());
+        assert!(header(&["239.1.1.1", "--bift-id", "1", "--bsl", "8"]).is_err());
+        // BFR-id beyond the 64 bits of BSL 1
+        assert!(header(&["239.1.1.1", "--bift-id", "1", "--bfr-ids", "65"]).is_err());
+        // BIFT-id is required
+        assert!(header(&["239.1.1.1"]).is_err());
+    }
+}
@@ -1,12 +1,32 @@
-use std::{collections::HashMap, net::IpAddr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::CString,
+    fmt,
+    fs::File,
+    io::BufReader,
+    net::{IpAddr, Ipv6Addr},
+};
 
 use anyhow::Error;
-use bier_common::bier::{BierHdr, Word0, Word1, Word2};
-use serde::{Deserialize, Serialize};
+use bier_common::{
+    bier::{bitstring_len, BierHdr, Word0, Word1, Word2, MAX_BITSTRING_LEN, MPLS_BIER_NIBBLE},
+    bierin6::BIERIN6_MAX_BSL,
+    bift::{
+        bit_to_bfr_id, set_bfr_id, BiftEntry, BiftInfo, BiftKey, BiftNeighbor, TeAdjacency, ADJ_ECMP,
+        ADJ_FORWARD_CONNECTED, ADJ_FORWARD_ROUTED, ADJ_LOCAL_DECAP, BIFT_MODE_BIER,
+        BIFT_MODE_TE, MAX_ECMP, NO_MATCH_DROP, NO_MATCH_PASS, NO_MATCH_REDIRECT,
+    },
+    egress::{
+        EgressEntry, ENCAP_ETHERNET, ENCAP_IPV6, ENCAP_MPLS, ENTROPY_FLOW_HASH, ENTROPY_RANDOM,
+        ENTROPY_STATIC, MTU_POLICY_DROP, MTU_POLICY_ICMP, MTU_POLICY_PASS,
+    },
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 
 // TODO: bit limitatiosn check?
-#[derive(Serialize,Deserialize,Debug)]
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
 pub struct SerdeBierHdr {
     pub bift_id: u32, // 20 bits
     pub tc: u8,       // 3 bits
@@ -21,20 +41,435 @@ pub struct SerdeBierHdr {
     pub dscp: u8,     // 6 bits
     pub proto: u8,    // 6 bits
     pub bfir_id: u16, // 16 bits
-    pub bitstring: [u8; 32] // 256 bits
+    pub bitstring: Vec<u8>, // 64 to 4096 bits, length given by bsl
+    #[serde(default)]
+    pub encap: Encap,
+    // Outer IPv6 addresses for BIERin6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub src: Option<Ipv6Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dst: Option<Ipv6Addr>,
+    // Packets that exceed the MTU once encapsulated
+    #[serde(default)]
+    pub mtu_policy: MtuPolicy,
+    // Static uses `entropy`, other modes compute it per packet
+    #[serde(default)]
+    pub entropy_mode: EntropyMode,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Default,ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Encap {
+    #[default]
+    Ethernet,
+    Mpls,
+    Ipv6,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Default,ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum MtuPolicy {
+    #[default]
+    Drop,
+    Pass,
+    Icmp,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Default,ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum EntropyMode {
+    #[default]
+    Static,
+    FlowHash,
+    Random,
 }
 
 pub type BierMapping = HashMap<IpAddr, SerdeBierHdr>;
 
+// Maps are written sorted by key, so saving the same config gives the same file
+fn sorted<K: Ord + Serialize, V: Serialize, S: Serializer>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+
+// Neighbor in the BIFT, all listed BFR-ids are forwarded to it and form its F-BM
+#[derive(Serialize,Deserialize,Debug)]
+pub struct SerdeBiftNeighbor {
+    pub bift_id: u32,
+    pub bfr_ids: Vec<u32>,
+    pub iface: String,
+    pub nh_mac: String,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BiftMode {
+    #[default]
+    Bier,
+    Te,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NoMatchPolicy {
+    #[default]
+    Drop,
+    Pass,
+    Redirect,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjacencyType {
+    ForwardConnected,
+    ForwardRouted,
+    LocalDecap,
+    Ecmp,
+}
+
+#[derive(Serialize,Deserialize,Debug)]
+pub struct SerdeNeighbor {
+    pub iface: String,
+    pub nh_mac: String,
+}
+
+// BIER-TE adjacency of a bit position
+#[derive(Serialize,Deserialize,Debug)]
+pub struct SerdeTeAdjacency {
+    pub bit: u32,
+    #[serde(rename = "type")]
+    pub kind: AdjacencyType,
+    // Neighbor for forward_connected and forward_routed
+    #[serde(default, flatten)]
+    pub nbr: Option<SerdeNeighbor>,
+    // Members for ecmp
+    #[serde(default)]
+    pub members: Vec<SerdeNeighbor>,
+}
+
+// Settings of a BIFT-id
+#[derive(Serialize,Deserialize,Debug,Default)]
+pub struct SerdeBiftId {
+    #[serde(default)]
+    pub mode: BiftMode,
+    // Accept MPLS BIER with this BIFT-id as label
+    #[serde(default)]
+    pub mpls: bool,
+    // Packets without matching bit, redirected to `redirect` with NoMatchPolicy::Redirect
+    #[serde(default)]
+    pub no_match: NoMatchPolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    #[serde(default)]
+    pub adjacencies: Vec<SerdeTeAdjacency>,
+}
 
 #[derive(Serialize,Deserialize,Debug)]
 pub struct BierConfig {
-    pub local: [u8; 32],
-    pub entries: BierMapping
+    pub local: Vec<u8>,
+    #[serde(serialize_with = "sorted")]
+    pub entries: BierMapping,
+    #[serde(default)]
+    pub bift: Vec<SerdeBiftNeighbor>,
+    #[serde(default, serialize_with = "sorted")]
+    pub bift_ids: HashMap<u32, SerdeBiftId>,
+    // Notify about every packet with expired TTL, counters are always kept
+    #[serde(default)]
+    pub ttl_notify: bool,
+    // Addresses of this BFR, received BIERin6 packets are only decapsulated if sent to one of them
+    #[serde(default)]
+    pub bierin6_local: Vec<Ipv6Addr>,
+}
+
+impl BierConfig {
+    pub fn from_file(file: &str) -> anyhow::Result<BierConfig> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(file)?))?)
+    }
+
+    pub fn local_bfer(&self) -> anyhow::Result<[u8; MAX_BITSTRING_LEN]> {
+        local_bitstring(&self.local)
+    }
+
+    // ICMP errors to IPv4 senders come from their own address, which is rejected as martian by default
+    pub fn icmp_v4(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(addr, hdr)| addr.is_ipv4() && hdr.mtu_policy == MtuPolicy::Icmp)
+    }
+
+    // Packets have to be passed to the TC forwarding program
+    pub fn forwarding(&self) -> bool {
+        !self.bift.is_empty() || self.bift_ids.values().any(|bift_id| bift_id.mode == BiftMode::Te)
+    }
+}
+
+// Local bits are aligned to the right end of the bitstring, so BFR-ids match for all BSLs
+pub fn local_bitstring(bits: &[u8]) -> anyhow::Result<[u8; MAX_BITSTRING_LEN]> {
+    let mut local = [0u8; MAX_BITSTRING_LEN];
+    if bits.len() > MAX_BITSTRING_LEN {
+        return Err(anyhow::Error::msg(format!(
+            "Local bitstring has {} bytes, at most {} are supported",
+            bits.len(),
+            MAX_BITSTRING_LEN
+        )));
+    }
+    local[MAX_BITSTRING_LEN - bits.len()..].copy_from_slice(bits);
+    Ok(local)
+}
+
+// Bitstring of `len` bytes with the bits of the given BFR-ids set
+pub fn bitstring_of(bfr_ids: &[u32], len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut bitstring = vec![0u8; len];
+    for bfr_id in bfr_ids {
+        if !set_bfr_id(&mut bitstring, *bfr_id) {
+            return Err(anyhow::Error::msg(format!("BFR-id {bfr_id} out of range")));
+        }
+    }
+    Ok(bitstring)
+}
+
+// BFR-ids of all set bits, ascending
+pub fn bfr_ids(bitstring: &[u8]) -> Vec<u32> {
+    let mut ids: Vec<u32> = (0..bitstring.len())
+        .flat_map(|byte| (0..8).map(move |bit| (byte, bit)))
+        .filter(|(byte, bit)| bitstring[*byte] & (0x80 >> bit) != 0)
+        .map(|(byte, bit)| bit_to_bfr_id(byte, bit, bitstring.len()))
+        .collect();
+    ids.sort();
+    ids
+}
+
+// Changed mappings and local bits of a reloaded config.
+// Only these are applied at runtime, other settings require a restart.
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    pub added: Vec<IpAddr>,
+    pub updated: Vec<IpAddr>,
+    pub removed: Vec<IpAddr>,
+    pub local: bool,
+    pub restart: bool,
+}
+
+impl ConfigDiff {
+    pub fn new(old: &BierConfig, new: &BierConfig) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        for (addr, hdr) in new.entries.iter() {
+            match old.entries.get(addr) {
+                None => diff.added.push(*addr),
+                Some(old_hdr) if old_hdr != hdr => diff.updated.push(*addr),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .entries
+            .keys()
+            .filter(|addr| !new.entries.contains_key(addr))
+            .copied()
+            .collect();
+        diff.added.sort();
+        diff.updated.sort();
+        diff.removed.sort();
+
+        let tables = |config: &BierConfig| {
+            (serde_json::to_value(&config.bift).ok(), serde_json::to_value(&config.bift_ids).ok())
+        };
+        diff.local = old.local != new.local;
+        diff.restart = old.ttl_notify != new.ttl_notify
+            || old.bierin6_local != new.bierin6_local
+            || tables(old) != tables(new);
+        diff
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |addrs: &[IpAddr]| addrs.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", ");
+        let mut parts = Vec::new();
+        if !self.added.is_empty() {
+            parts.push(format!("added {}", list(&self.added)));
+        }
+        if !self.updated.is_empty() {
+            parts.push(format!("updated {}", list(&self.updated)));
+        }
+        if !self.removed.is_empty() {
+            parts.push(format!("removed {}", list(&self.removed)));
+        }
+        if self.local {
+            parts.push("local bits changed".to_string());
+        }
+        if parts.is_empty() {
+            parts.push("no mapping changed".to_string());
+        }
+        if self.restart {
+            parts.push("other settings need a restart".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+pub fn parse_mac(mac: &str) -> anyhow::Result<[u8; 6]> {
+    let bytes = mac
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|e| anyhow::Error::msg(format!("Invalid MAC address {mac}: {e}")))?;
+
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::Error::msg(format!("Invalid MAC address {mac}: expected 6 bytes")))
+}
+
+impl SerdeNeighbor {
+    pub fn to_bift_neighbor(&self) -> anyhow::Result<BiftNeighbor> {
+        to_bift_neighbor(&self.iface, &self.nh_mac)
+    }
+}
+
+fn ifindex(iface: &str) -> anyhow::Result<u32> {
+    let c_iface = CString::new(iface)?;
+    let ifindex = unsafe { libc::if_nametoindex(c_iface.as_ptr()) };
+    if ifindex == 0 {
+        return Err(anyhow::Error::msg(format!("Unknown interface {iface}")));
+    }
+    Ok(ifindex)
+}
+
+fn to_bift_neighbor(iface: &str, nh_mac: &str) -> anyhow::Result<BiftNeighbor> {
+    let ifindex = ifindex(iface)?;
+
+    let src_mac = std::fs::read_to_string(format!("/sys/class/net/{iface}/address"))
+        .map_err(anyhow::Error::from)
+        .and_then(|mac| parse_mac(mac.trim()))?;
+
+    Ok(BiftNeighbor {
+        ifindex,
+        src_mac,
+        nh_mac: parse_mac(nh_mac)?,
+    })
+}
+
+impl SerdeBiftId {
+    pub fn to_bift_info(&self) -> anyhow::Result<BiftInfo> {
+        let mode = match self.mode {
+            BiftMode::Bier => BIFT_MODE_BIER,
+            BiftMode::Te => BIFT_MODE_TE,
+        };
+        let mut info = BiftInfo::new(mode, self.mpls);
+        info.no_match = match self.no_match {
+            NoMatchPolicy::Drop => NO_MATCH_DROP,
+            NoMatchPolicy::Pass => NO_MATCH_PASS,
+            NoMatchPolicy::Redirect => {
+                let iface = self
+                    .redirect
+                    .as_ref()
+                    .ok_or_else(|| anyhow::Error::msg("Redirect policy requires redirect interface"))?;
+                info.redirect_ifindex = ifindex(iface)?;
+                NO_MATCH_REDIRECT
+            }
+        };
+        for adj in self.adjacencies.iter() {
+            if !set_bfr_id(&mut info.adjacent_bits, adj.bit) {
+                return Err(anyhow::Error::msg(format!("Bit {} out of range", adj.bit)));
+            }
+        }
+        Ok(info)
+    }
+
+    pub fn to_te_adjacencies(&self, bift_id: u32) -> anyhow::Result<Vec<(BiftKey, TeAdjacency)>> {
+        self.adjacencies
+            .iter()
+            .map(|adj| {
+                let key = BiftKey {
+                    bift_id,
+                    bfr_id: adj.bit,
+                };
+                let missing = || anyhow::Error::msg(format!("Adjacency of bit {} needs iface and nh_mac", adj.bit));
+                let value = match adj.kind {
+                    AdjacencyType::ForwardConnected => TeAdjacency::new(
+                        ADJ_FORWARD_CONNECTED,
+                        &[adj.nbr.as_ref().ok_or_else(missing)?.to_bift_neighbor()?],
+                    ),
+                    AdjacencyType::ForwardRouted => TeAdjacency::new(
+                        ADJ_FORWARD_ROUTED,
+                        &[adj.nbr.as_ref().ok_or_else(missing)?.to_bift_neighbor()?],
+                    ),
+                    AdjacencyType::LocalDecap => TeAdjacency::new(ADJ_LOCAL_DECAP, &[]),
+                    AdjacencyType::Ecmp => {
+                        if adj.members.is_empty() || adj.members.len() > MAX_ECMP {
+                            return Err(anyhow::Error::msg(format!(
+                                "ECMP adjacency of bit {} needs 1 to {MAX_ECMP} members",
+                                adj.bit
+                            )));
+                        }
+                        let nbrs = adj
+                            .members
+                            .iter()
+                            .map(SerdeNeighbor::to_bift_neighbor)
+                            .collect::<anyhow::Result<Vec<_>>>()?;
+                        TeAdjacency::new(ADJ_ECMP, &nbrs)
+                    }
+                };
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+impl SerdeBiftNeighbor {
+    pub fn to_bift_entries(&self) -> anyhow::Result<Vec<(BiftKey, BiftEntry)>> {
+        let mut entry = BiftEntry::new(to_bift_neighbor(&self.iface, &self.nh_mac)?);
+        for bfr_id in self.bfr_ids.iter() {
+            if !set_bfr_id(&mut entry.fbm, *bfr_id) {
+                return Err(anyhow::Error::msg(format!("BFR-id {bfr_id} out of range")));
+            }
+        }
+
+        Ok(self
+            .bfr_ids
+            .iter()
+            .map(|bfr_id| {
+                (
+                    BiftKey {
+                        bift_id: self.bift_id,
+                        bfr_id: *bfr_id,
+                    },
+                    entry,
+                )
+            })
+            .collect())
+    }
 }
 
 
 impl SerdeBierHdr {
+    // Header without any bits set, other fields as for new mappings of the client
+    pub fn new(bift_id: u32, bsl: u8, proto: u8) -> SerdeBierHdr {
+        SerdeBierHdr {
+            bift_id,
+            tc: 0,
+            s: false,
+            ttl: 64,
+            nibble: 0,
+            version: 0,
+            bsl,
+            entropy: 0,
+            oam: 0,
+            rsv: 0,
+            dscp: 0,
+            proto,
+            bfir_id: 0,
+            bitstring: vec![0; bitstring_len(bsl)],
+            encap: Encap::default(),
+            src: None,
+            dst: None,
+            mtu_policy: MtuPolicy::default(),
+            entropy_mode: EntropyMode::default(),
+        }
+    }
+
     pub fn to_bier_hdr(&self) -> anyhow::Result<BierHdr> {
 
         let word0 = Word0::new()
@@ -56,14 +491,64 @@ impl SerdeBierHdr {
             .with_proto_checked(self.proto).map_err(|e| anyhow::Error::msg(e.to_string()))?
             .with_bfir_id_checked(self.bfir_id).map_err(|e| anyhow::Error::msg(e.to_string()))?;
 
-        let hdr = BierHdr{
+        let mut hdr = BierHdr{
             word0,
             word1,word2,
-            bitstring: self.bitstring
+            bitstring: [0; MAX_BITSTRING_LEN]
         };
+        if bitstring_len(self.bsl) == 0 {
+            return Err(anyhow::Error::msg(format!("BSL {} is not defined, valid BSLs are 1 to 7", self.bsl)));
+        }
+        if !hdr.set_bitstring(&self.bitstring) {
+            return Err(anyhow::Error::msg(format!(
+                "Bitstring has {} bytes, BSL {} requires {}",
+                self.bitstring.len(),
+                self.bsl,
+                bitstring_len(self.bsl)
+            )));
+        }
         Ok(hdr)
     }
 
+    pub fn to_egress_entry(&self) -> anyhow::Result<EgressEntry> {
+        let encap = match self.encap {
+            Encap::Ethernet => ENCAP_ETHERNET,
+            Encap::Mpls => {
+                if self.nibble != MPLS_BIER_NIBBLE {
+                    return Err(anyhow::Error::msg(format!(
+                        "MPLS encapsulation requires nibble {MPLS_BIER_NIBBLE}"
+                    )));
+                }
+                ENCAP_MPLS
+            }
+            Encap::Ipv6 => {
+                if self.src.is_none() || self.dst.is_none() {
+                    return Err(anyhow::Error::msg("IPv6 encapsulation requires src and dst"));
+                }
+                if self.bsl > BIERIN6_MAX_BSL {
+                    return Err(anyhow::Error::msg(format!(
+                        "IPv6 encapsulation supports at most BSL {BIERIN6_MAX_BSL}"
+                    )));
+                }
+                ENCAP_IPV6
+            }
+        };
+        let src = self.src.unwrap_or(Ipv6Addr::UNSPECIFIED).octets();
+        let dst = self.dst.unwrap_or(Ipv6Addr::UNSPECIFIED).octets();
+        let mut entry = EgressEntry::new(self.to_bier_hdr()?.into_bytes(), encap, src, dst);
+        entry.mtu_policy = match self.mtu_policy {
+            MtuPolicy::Drop => MTU_POLICY_DROP,
+            MtuPolicy::Pass => MTU_POLICY_PASS,
+            MtuPolicy::Icmp => MTU_POLICY_ICMP,
+        };
+        entry.entropy_mode = match self.entropy_mode {
+            EntropyMode::Static => ENTROPY_STATIC,
+            EntropyMode::FlowHash => ENTROPY_FLOW_HASH,
+            EntropyMode::Random => ENTROPY_RANDOM,
+        };
+        Ok(entry)
+    }
+
     pub fn to_string_vec(&self) -> Vec<String> {
         let json: Value = serde_json::to_value(self).unwrap();
         json.as_object()
@@ -74,3 +559,74 @@ impl SerdeBierHdr {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> BierConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn entry(bift_id: u32) -> String {
+        serde_json::to_string(&SerdeBierHdr::new(bift_id, 1, 4)).unwrap()
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let old = config(&format!(
+            r#"{{"local": [1], "entries": {{"239.1.1.1": {}, "239.1.1.2": {}, "ff3e::1": {}}}}}"#,
+            entry(1),
+            entry(2),
+            entry(3)
+        ));
+        let new = config(&format!(
+            r#"{{"local": [2], "entries": {{"239.1.1.2": {}, "ff3e::1": {}, "239.1.1.3": {}}}}}"#,
+            entry(20),
+            entry(3),
+            entry(4)
+        ));
+
+        let diff = ConfigDiff::new(&old, &new);
+        assert_eq!(diff.added, vec!["239.1.1.3".parse::<IpAddr>().unwrap()]);
+        assert_eq!(diff.updated, vec!["239.1.1.2".parse::<IpAddr>().unwrap()]);
+        assert_eq!(diff.removed, vec!["239.1.1.1".parse::<IpAddr>().unwrap()]);
+        assert!(diff.local);
+        assert!(!diff.restart);
+        assert_eq!(
+            diff.to_string(),
+            "added 239.1.1.3, updated 239.1.1.2, removed 239.1.1.1, local bits changed"
+        );
+    }
+
+    #[test]
+    fn test_sorted_entries() {
+        let config = config(&format!(
+            r#"{{"local": [], "entries": {{"ff3e::1": {}, "239.1.1.2": {}, "239.1.1.1": {}}}, "bift_ids": {{"3": {{}}, "1": {{}}}}}}"#,
+            entry(1),
+            entry(2),
+            entry(3)
+        ));
+        let json = serde_json::to_string(&config).unwrap();
+        let position = |key: &str| json.find(key).unwrap();
+        assert!(position("239.1.1.1") < position("239.1.1.2"));
+        assert!(position("239.1.1.2") < position("ff3e::1"));
+        assert!(position(r#""1":"#) < position(r#""3":"#));
+    }
+
+    #[test]
+    fn test_config_diff_restart() {
+        let old = config(r#"{"local": [1], "entries": {}}"#);
+        assert_eq!(ConfigDiff::new(&old, &old).to_string(), "no mapping changed");
+
+        // Settings that are not applied at runtime
+        for new in [
+            r#"{"local": [1], "entries": {}, "ttl_notify": true}"#,
+            r#"{"local": [1], "entries": {}, "bierin6_local": ["2001:db8::2"]}"#,
+            r#"{"local": [1], "entries": {}, "bift_ids": {"1": {"mode": "te"}}}"#,
+        ] {
+            let diff = ConfigDiff::new(&old, &config(new));
+            assert!(diff.restart);
+            assert_eq!(diff.to_string(), "no mapping changed, other settings need a restart");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// Local control socket of the daemon.
+// Every line is a JSON request, answered by a single line JSON response.
+//
+//     {"op": "add", "group": "239.1.1.1", "header": { "bift_id": 100001, ... }}
+//     {"ok": true}
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::IpAddr,
+    os::unix::net::{UnixListener, UnixStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::Context;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use bier_common::counter::Counter;
+
+use crate::{config::SerdeBierHdr, mapping::Mappings};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    // Mappings from group to BIER header
+    List,
+    // Add or update a mapping
+    Add { group: IpAddr, header: SerdeBierHdr },
+    Del { group: IpAddr },
+    Local,
+    SetLocal { local: Vec<u8> },
+    Counters,
+    ResetCounters,
+}
+
+// Packets and bytes of a counter, e.g. the group of a mapping or "BFIR-id 3" on receive
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CounterRow {
+    pub key: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReasonRow {
+    pub reason: String,
+    pub drop: bool,
+    pub packets: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Counters {
+    pub egress: Vec<CounterRow>,
+    pub rx: Vec<CounterRow>,
+    pub ttl_expired: Vec<CounterRow>,
+    pub reasons: Vec<ReasonRow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    fn from_result(result: anyhow::Result<Option<Value>>) -> Response {
+        match result {
+            Ok(data) => Response {
+                ok: true,
+                data,
+                error: None,
+            },
+            Err(e) => Response {
+                ok: false,
+                data: None,
+                error: Some(format!("{e:#}")),
+            },
+        }
+    }
+}
+
+// Apply a request to the mappings, the same operations are used by the TUI
+pub fn handle(mappings: &mut Mappings, request: Request) -> anyhow::Result<Option<Value>> {
+    match request {
+        Request::List => Ok(Some(serde_json::to_value(mappings.entries())?)),
+        Request::Add { group, header } => {
+            mappings.set_mapping(group, header)?;
+            Ok(None)
+        }
+        Request::Del { group } => {
+            mappings.remove_mapping(group)?;
+            Ok(None)
+        }
+        Request::Local => Ok(Some(json!({ "local": mappings.get_local() }))),
+        Request::SetLocal { local } => {
+            mappings.set_local(local)?;
+            Ok(None)
+        }
+        Request::Counters => Ok(Some(serde_json::to_value(counters(mappings))?)),
+        Request::ResetCounters => {
+            mappings.reset_counters()?;
+            Ok(None)
+        }
+    }
+}
+
+pub fn counters(mappings: &Mappings) -> Counters {
+    let row = |key: String, counter: &Counter| CounterRow {
+        key,
+        packets: counter.packets,
+        bytes: counter.bytes,
+    };
+
+    Counters {
+        egress: mappings
+            .get_egress_counters()
+            .iter()
+            .map(|(group, counter)| row(group.to_string(), counter))
+            .collect(),
+        rx: mappings
+            .get_rx_data()
+            .iter()
+            .map(|rx| CounterRow {
+                key: format!("{} {}", rx.kind, rx.key),
+                packets: rx.packet_count,
+                bytes: rx.byte_count,
+            })
+            .collect(),
+        ttl_expired: mappings
+            .get_ttl_expired()
+            .iter()
+            .map(|(bift_id, counter)| row(bift_id.to_string(), counter))
+            .collect(),
+        reasons: mappings
+            .get_reasons()
+            .iter()
+            .map(|(reason, packets)| ReasonRow {
+                reason: reason.name().to_string(),
+                drop: reason.is_drop(),
+                packets: *packets,
+            })
+            .collect(),
+    }
+}
+
+fn serve_client(stream: UnixStream, mappings: &Mutex<Mappings>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = serde_json::from_str::<Request>(&line)
+            .context("Invalid request")
+            .and_then(|request| handle(&mut mappings.lock().unwrap(), request));
+        let response = serde_json::to_string(&Response::from_result(result))?;
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+// A socket nobody listens on is left over from a daemon that did not exit cleanly
+fn remove_stale(path: &str) -> anyhow::Result<()> {
+    match UnixStream::connect(path) {
+        Ok(_) => Err(anyhow::Error::msg(format!("A daemon is already running on control socket {path}"))),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            fs::remove_file(path).with_context(|| format!("Failed removing stale control socket {path}"))
+        }
+        Err(_) => Ok(()),
+    }
+}
+
+// Serve the control socket at `path` in the background, a stale socket file is replaced
+pub fn spawn(path: &str, mappings: Arc<Mutex<Mappings>>) -> anyhow::Result<()> {
+    remove_stale(path)?;
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed binding control socket {path}"))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let mappings = mappings.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_client(stream, &mappings) {
+                            warn!("Control client failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Control socket failed: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_parsing() {
+        let request: Request = serde_json::from_str(
+            r#"{"op": "add", "group": "239.1.1.1", "header": {"bift_id": 1, "tc": 0, "s": false, "ttl": 64, "nibble": 0, "version": 0, "bsl": 1, "entropy": 0, "oam": 0, "rsv": 0, "dscp": 0, "proto": 4, "bfir_id": 3, "bitstring": [0, 0, 0, 0, 0, 0, 0, 1]}}"#,
+        )
+        .unwrap();
+        match request {
+            Request::Add { group, header } => {
+                assert_eq!(group, "239.1.1.1".parse::<IpAddr>().unwrap());
+                assert_eq!(header.bfir_id, 3);
+            }
+            other => panic!("unexpected request {other:?}"),
+        }
+
+        assert!(matches!(serde_json::from_str(r#"{"op": "list"}"#), Ok(Request::List)));
+        assert!(matches!(serde_json::from_str(r#"{"op": "reset_counters"}"#), Ok(Request::ResetCounters)));
+        assert!(matches!(
+            serde_json::from_str(r#"{"op": "del", "group": "ff3e::1"}"#),
+            Ok(Request::Del { group: IpAddr::V6(_) })
+        ));
+        match serde_json::from_str(r#"{"op": "set_local", "local": [1, 2]}"#) {
+            Ok(Request::SetLocal { local }) => assert_eq!(local, vec![1, 2]),
+            other => panic!("unexpected request {other:?}"),
+        }
+
+        // Unknown operations and missing fields are rejected
+        assert!(serde_json::from_str::<Request>(r#"{"op": "flush"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"op": "del"}"#).is_err());
+    }
+
+    #[test]
+    fn test_remove_stale() {
+        let path = std::env::temp_dir().join(format!("bier-test-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        assert!(remove_stale(path).is_ok());
+
+        // Socket of a running daemon is kept
+        let listener = UnixListener::bind(path).unwrap();
+        assert!(remove_stale(path).is_err());
+        assert!(fs::metadata(path).is_ok());
+
+        // The file stays behind when the listener is gone
+        drop(listener);
+        assert!(remove_stale(path).is_ok());
+        assert!(fs::metadata(path).is_err());
+    }
+
+    #[test]
+    fn test_response() {
+        let ok = Response::from_result(Ok(None));
+        assert_eq!(serde_json::to_string(&ok).unwrap(), r#"{"ok":true}"#);
+
+        let data = Response::from_result(Ok(Some(json!({ "local": [1] }))));
+        assert_eq!(serde_json::to_string(&data).unwrap(), r#"{"ok":true,"data":{"local":[1]}}"#);
+
+        // Errors keep their context
+        let error = Response::from_result(Err(anyhow::Error::msg("BFR-id 300 out of range").context("Invalid request")));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"ok":false,"error":"Invalid request: BFR-id 300 out of range"}"#
+        );
+    }
+}
@@ -0,0 +1,368 @@
+// Editing of a mapping in the TUI. Changed fields are validated with the `_checked` setters
+// of `SerdeBierHdr::to_bier_hdr` and the encapsulation checks of `to_egress_entry` before they
+// are accepted, the data plane is only changed when the mapping is applied.
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use bier_common::bier::{bitstring_len, BIER_PROTO_IPV4, BIER_PROTO_IPV6, MPLS_BIER_NIBBLE};
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::{
+    config::{bfr_ids, bitstring_of, EntropyMode, Encap, MtuPolicy, SerdeBierHdr},
+    mapping::Mappings,
+};
+
+pub const FIELDS: [&str; 20] = [
+    "group",
+    "bift_id",
+    "tc",
+    "s",
+    "ttl",
+    "nibble",
+    "version",
+    "bsl",
+    "entropy",
+    "oam",
+    "rsv",
+    "dscp",
+    "proto",
+    "bfir_id",
+    "encap",
+    "src",
+    "dst",
+    "mtu_policy",
+    "entropy_mode",
+    "bitstring",
+];
+
+const BITSTRING: usize = FIELDS.len() - 1;
+
+pub struct Editor {
+    pub group: String,
+    // Groups are only editable for new mappings
+    pub new: bool,
+    pub hdr: SerdeBierHdr,
+    pub selected: usize,
+    // Text of the selected field while it is typed
+    pub input: Option<String>,
+    // BFR-id under the cursor of the bitstring
+    pub bfr_id: u32,
+    pub error: Option<String>,
+}
+
+fn next<T: ValueEnum + PartialEq>(value: &T) -> T {
+    let variants = T::value_variants();
+    let index = variants.iter().position(|variant| variant == value).unwrap_or(0);
+    variants[(index + 1) % variants.len()].clone()
+}
+
+impl Editor {
+    pub fn edit(group: IpAddr, hdr: SerdeBierHdr) -> Editor {
+        Editor {
+            group: group.to_string(),
+            new: false,
+            hdr,
+            selected: 1,
+            input: None,
+            bfr_id: 1,
+            error: None,
+        }
+    }
+
+    // New mappings start from the header of the selected one without any bits set
+    pub fn add(template: Option<SerdeBierHdr>) -> Editor {
+        let mut hdr = template.unwrap_or_else(|| SerdeBierHdr::new(0, 1, BIER_PROTO_IPV4));
+        hdr.bitstring = vec![0; bitstring_len(hdr.bsl)];
+        Editor {
+            group: String::new(),
+            new: true,
+            hdr,
+            selected: 0,
+            input: Some(String::new()),
+            bfr_id: 1,
+            error: None,
+        }
+    }
+
+    fn bits(&self) -> u32 {
+        self.hdr.bitstring.len() as u32 * 8
+    }
+
+    // Lines of all fields, the selected one is marked
+    pub fn lines(&self) -> Vec<String> {
+        let json = serde_json::to_value(&self.hdr).unwrap_or(Value::Null);
+        FIELDS
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let value = match (&self.input, i) {
+                    (Some(input), i) if i == self.selected => format!("{input}_"),
+                    (_, 0) => self.group.clone(),
+                    (_, BITSTRING) => {
+                        let ids = bfr_ids(&self.hdr.bitstring);
+                        let mark = if ids.contains(&self.bfr_id) { "x" } else { " " };
+                        let ids = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+                        format!("BFR-ids [{ids}] | BFR-id {} [{mark}]", self.bfr_id)
+                    }
+                    _ => json.get(field).map(Value::to_string).unwrap_or_default(),
+                };
+                let cursor = if i == self.selected { ">" } else { " " };
+                format!("{cursor} {field}: {value}")
+            })
+            .collect()
+    }
+
+    pub fn up(&mut self) {
+        if self.input.is_none() && self.selected > usize::from(!self.new) {
+            self.selected -= 1;
+        }
+    }
+
+    pub fn down(&mut self) {
+        if self.input.is_none() && self.selected < BITSTRING {
+            self.selected += 1;
+        }
+    }
+
+    // Move the cursor of the bitstring
+    pub fn left(&mut self) {
+        if self.selected == BITSTRING && self.bfr_id > 1 {
+            self.bfr_id -= 1;
+        }
+    }
+
+    pub fn right(&mut self) {
+        if self.selected == BITSTRING && self.bfr_id < self.bits() {
+            self.bfr_id += 1;
+        }
+    }
+
+    pub fn toggle_bit(&mut self) {
+        if self.selected != BITSTRING {
+            return;
+        }
+        let mut ids = bfr_ids(&self.hdr.bitstring);
+        match ids.iter().position(|id| *id == self.bfr_id) {
+            Some(index) => {
+                ids.remove(index);
+            }
+            None => ids.push(self.bfr_id),
+        }
+        if let Ok(bitstring) = bitstring_of(&ids, self.hdr.bitstring.len()) {
+            self.hdr.bitstring = bitstring;
+        }
+    }
+
+    // Enter starts typing, toggles and cycles fields without free text
+    pub fn enter(&mut self) {
+        self.error = None;
+        if let Some(input) = self.input.take() {
+            if let Err(e) = self.set(self.selected, &input) {
+                self.error = Some(format!("{}: {e:#}", FIELDS[self.selected]));
+                self.input = Some(input);
+            }
+            return;
+        }
+        match FIELDS[self.selected] {
+            "s" => self.hdr.s = !self.hdr.s,
+            "encap" => {
+                self.hdr.encap = next::<Encap>(&self.hdr.encap);
+                // MPLS sets S and the nibble, like `bier add --encap mpls`
+                let mpls = self.hdr.encap == Encap::Mpls;
+                self.hdr.s = mpls;
+                self.hdr.nibble = if mpls { MPLS_BIER_NIBBLE } else { 0 };
+            }
+            "mtu_policy" => self.hdr.mtu_policy = next::<MtuPolicy>(&self.hdr.mtu_policy),
+            "entropy_mode" => self.hdr.entropy_mode = next::<EntropyMode>(&self.hdr.entropy_mode),
+            "bitstring" => self.toggle_bit(),
+            "group" => self.input = Some(self.group.clone()),
+            field => {
+                let json = serde_json::to_value(&self.hdr).unwrap_or(Value::Null);
+                self.input = Some(match json.get(field) {
+                    Some(Value::String(text)) => text.clone(),
+                    Some(Value::Null) | None => String::new(),
+                    Some(value) => value.to_string(),
+                });
+            }
+        }
+    }
+
+    pub fn type_char(&mut self, c: char) {
+        if let Some(input) = self.input.as_mut() {
+            input.push(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(input) = self.input.as_mut() {
+            input.pop();
+        }
+    }
+
+    // Esc cancels typing, returns true if the editor should be closed
+    pub fn escape(&mut self) -> bool {
+        self.error = None;
+        match self.input.take() {
+            Some(_) => self.new && self.group.is_empty(),
+            None => true,
+        }
+    }
+
+    fn set(&mut self, field: usize, text: &str) -> anyhow::Result<()> {
+        if field == 0 {
+            let group: IpAddr = text.trim().parse()?;
+            self.hdr.proto = match group {
+                IpAddr::V4(_) => BIER_PROTO_IPV4,
+                IpAddr::V6(_) => BIER_PROTO_IPV6,
+            };
+            self.group = group.to_string();
+            return Ok(());
+        }
+
+        let mut json = serde_json::to_value(&self.hdr)?;
+        let value = match serde_json::from_str::<Value>(text) {
+            Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+            _ if text.trim().is_empty() => Value::Null,
+            _ => Value::String(text.trim().to_string()),
+        };
+        json[FIELDS[field]] = value;
+
+        let mut hdr: SerdeBierHdr = serde_json::from_value(json)?;
+        if hdr.bsl != self.hdr.bsl {
+            // BFR-ids are kept as far as they fit into the new BitString
+            let len = bitstring_len(hdr.bsl);
+            let ids: Vec<u32> = bfr_ids(&hdr.bitstring)
+                .into_iter()
+                .filter(|id| (*id as usize) <= len * 8)
+                .collect();
+            hdr.bitstring = bitstring_of(&ids, len)?;
+        }
+        hdr.to_bier_hdr()?;
+        // src and dst are typed one after the other, missing ones are only required on apply
+        let mut check = hdr.clone();
+        check.src.get_or_insert(Ipv6Addr::UNSPECIFIED);
+        check.dst.get_or_insert(Ipv6Addr::UNSPECIFIED);
+        check.to_egress_entry()?;
+
+        self.hdr = hdr;
+        self.bfr_id = self.bfr_id.min(self.bits()).max(1);
+        Ok(())
+    }
+
+    // Write the mapping to the data plane, the result is shown in the title
+    pub fn apply(&self, mappings: &mut Mappings) -> anyhow::Result<String> {
+        let group: IpAddr = self
+            .group
+            .parse()
+            .map_err(|_| anyhow::Error::msg("group is not an IP address"))?;
+        if self.new && mappings.entries().contains_key(&group) {
+            return Err(anyhow::Error::msg(format!("{group} is already mapped")));
+        }
+        mappings.set_mapping(group, self.hdr.clone())?;
+        Ok(format!("{} {group}", if self.new { "added" } else { "updated" }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> usize {
+        FIELDS.iter().position(|field| *field == name).unwrap()
+    }
+
+    fn editor() -> Editor {
+        let mut hdr = SerdeBierHdr::new(1, 2, BIER_PROTO_IPV4);
+        hdr.bitstring = bitstring_of(&[1, 100], 16).unwrap();
+        Editor::edit("239.1.1.1".parse().unwrap(), hdr)
+    }
+
+    #[test]
+    fn test_set() {
+        let mut edit = editor();
+        edit.set(field("ttl"), "32").unwrap();
+        assert_eq!(edit.hdr.ttl, 32);
+        edit.set(field("mtu_policy"), "icmp").unwrap();
+        assert_eq!(edit.hdr.mtu_policy, MtuPolicy::Icmp);
+
+        // Group decides the next protocol
+        edit.set(field("group"), " ff3e::1 ").unwrap();
+        assert_eq!(edit.group, "ff3e::1");
+        assert_eq!(edit.hdr.proto, BIER_PROTO_IPV6);
+
+        // BFR-ids that do not fit into the new BitString are dropped
+        edit.bfr_id = 100;
+        edit.set(field("bsl"), "1").unwrap();
+        assert_eq!(bfr_ids(&edit.hdr.bitstring), vec![1]);
+        assert_eq!(edit.bfr_id, 64);
+    }
+
+    #[test]
+    fn test_set_invalid() {
+        let mut edit = editor();
+        let hdr = edit.hdr.clone();
+        // TC has 3 bits, BSL 8 is not defined
+        assert!(edit.set(field("tc"), "8").is_err());
+        assert!(edit.set(field("bsl"), "8").is_err());
+        assert!(edit.set(field("ttl"), "ttl").is_err());
+        assert!(edit.set(field("group"), "239.1.1").is_err());
+        // MPLS needs its nibble, BIERin6 supports at most BSL 5
+        edit.hdr.encap = Encap::Mpls;
+        edit.hdr.nibble = MPLS_BIER_NIBBLE;
+        assert!(edit.set(field("nibble"), "0").is_err());
+        edit.hdr.encap = Encap::Ipv6;
+        assert!(edit.set(field("bsl"), "6").is_err());
+        edit.hdr = hdr.clone();
+        assert_eq!(edit.hdr, hdr);
+        assert_eq!(edit.group, "239.1.1.1");
+
+        // The input is kept to be corrected
+        edit.selected = field("tc");
+        edit.input = Some("8".to_string());
+        edit.enter();
+        assert_eq!(edit.input.as_deref(), Some("8"));
+        assert!(edit.error.as_deref().unwrap().starts_with("tc: "));
+    }
+
+    #[test]
+    fn test_cycle_encap() {
+        let mut edit = editor();
+        edit.selected = field("encap");
+        edit.enter();
+        assert_eq!(edit.hdr.encap, Encap::Mpls);
+        assert!(edit.hdr.s);
+        assert_eq!(edit.hdr.nibble, MPLS_BIER_NIBBLE);
+        edit.hdr.to_egress_entry().unwrap();
+
+        // src and dst of IPv6 encapsulation are set one at a time
+        edit.enter();
+        assert_eq!(edit.hdr.encap, Encap::Ipv6);
+        assert!(!edit.hdr.s);
+        assert_eq!(edit.hdr.nibble, 0);
+        edit.set(field("src"), "2001:db8::1").unwrap();
+        edit.set(field("dst"), "2001:db8::2").unwrap();
+        edit.hdr.to_egress_entry().unwrap();
+    }
+
+    #[test]
+    fn test_toggle_bit() {
+        let mut edit = editor();
+        // Only the BitString row toggles bits
+        edit.bfr_id = 2;
+        edit.toggle_bit();
+        assert_eq!(bfr_ids(&edit.hdr.bitstring), vec![1, 100]);
+
+        edit.selected = BITSTRING;
+        edit.toggle_bit();
+        assert_eq!(bfr_ids(&edit.hdr.bitstring), vec![1, 2, 100]);
+        edit.bfr_id = 100;
+        edit.toggle_bit();
+        assert_eq!(bfr_ids(&edit.hdr.bitstring), vec![1, 2]);
+
+        // The cursor stays within the BitString
+        edit.bfr_id = 128;
+        edit.right();
+        assert_eq!(edit.bfr_id, 128);
+    }
+}
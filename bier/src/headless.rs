@@ -0,0 +1,135 @@
+// Daemon mode without the TUI, e.g. under systemd, in a container or over SSH.
+// Counter snapshots are written as JSON lines until SIGINT or SIGTERM:
+//
+//     {"time":1700000000,"egress":[...],"rx":[...],"ttl_expired":[...],"reasons":[...]}
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use serde::Serialize;
+use tokio::{
+    signal::{
+        self,
+        unix::{signal as unix_signal, SignalKind},
+    },
+    time::interval,
+};
+
+use crate::{
+    control::{counters, Counters},
+    mapping::Mappings,
+    reload::ConfigWatch,
+};
+
+#[derive(Serialize)]
+struct Snapshot {
+    // Seconds since the Unix epoch
+    time: u64,
+    #[serde(flatten)]
+    counters: Counters,
+}
+
+// `stats` is a file the snapshots are appended to, `-` for stdout
+pub async fn run_headless(
+    shared: Arc<Mutex<Mappings>>,
+    mut watch: ConfigWatch,
+    stats: &str,
+    stats_interval: Duration,
+) -> anyhow::Result<()> {
+    let mut out: Box<dyn Write + Send> = match stats {
+        "-" => Box::new(io::stdout()),
+        path => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed opening stats file {path}"))?,
+        ),
+    };
+    let mut terminate = unix_signal(SignalKind::terminate())?;
+    // Reloads and TTL expiries are handled more often than snapshots are written
+    let mut poll = interval(Duration::from_secs(1));
+    let mut snapshot = interval(stats_interval);
+    let mut failed_reload: Option<String> = None;
+
+    loop {
+        tokio::select! {
+            _ = signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+            _ = poll.tick() => {
+                let mut mappings = shared.lock().unwrap();
+                if watch.changed() {
+                    match mappings.reload() {
+                        Ok(diff) => {
+                            watch.loaded();
+                            failed_reload = None;
+                            info!("Reloaded config: {diff}");
+                        }
+                        // Retried every poll until the file is fixed, the same error is logged once
+                        Err(e) => {
+                            let error = format!("{e:#}");
+                            if failed_reload.as_ref() != Some(&error) {
+                                warn!("Reload failed: {error}");
+                            }
+                            failed_reload = Some(error);
+                        }
+                    }
+                }
+                for expiry in mappings.poll_ttl_events() {
+                    info!("TTL expired: BIFT-id {} from BFIR-id {}", expiry.bift_id, expiry.bfir_id);
+                }
+            }
+            _ = snapshot.tick() => {
+                let line = Snapshot {
+                    time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                    counters: counters(&shared.lock().unwrap()),
+                };
+                writeln!(out, "{}", serde_json::to_string(&line)?)?;
+                out.flush()?;
+            }
+        }
+    }
+
+    info!("Shutting down");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control::{CounterRow, ReasonRow};
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_line() {
+        let snapshot = Snapshot {
+            time: 1700000000,
+            counters: Counters {
+                egress: vec![CounterRow {
+                    key: "239.1.1.1".to_string(),
+                    packets: 2,
+                    bytes: 200,
+                }],
+                rx: Vec::new(),
+                ttl_expired: Vec::new(),
+                reasons: vec![ReasonRow {
+                    reason: "TTL expired".to_string(),
+                    drop: true,
+                    packets: 1,
+                }],
+            },
+        };
+
+        // Counters are flattened next to the time, one line per snapshot
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            r#"{"time":1700000000,"egress":[{"key":"239.1.1.1","packets":2,"bytes":200}],"rx":[],"ttl_expired":[],"reasons":[{"reason":"TTL expired","drop":true,"packets":1}]}"#
+        );
+    }
+}
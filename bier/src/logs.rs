@@ -0,0 +1,188 @@
+// Log records of userspace and the eBPF programs (via aya-log) for the TUI.
+// Records are written to stderr until the TUI owns the terminal, afterwards they are only kept
+// for the log pane, optionally mirrored to a file. All records are kept, the level of the pane
+// only decides which ones are shown.
+
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Records kept for the log pane, older ones are dropped
+pub const CAPACITY: usize = 1000;
+
+const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+struct State {
+    records: VecDeque<(Level, String)>,
+    // Level of the log pane
+    level: LevelFilter,
+    // Cleared while the TUI owns the terminal
+    stderr: bool,
+}
+
+#[derive(Clone)]
+pub struct LogBuffer(Arc<Mutex<State>>);
+
+impl Default for LogBuffer {
+    fn default() -> LogBuffer {
+        LogBuffer(Arc::new(Mutex::new(State {
+            records: VecDeque::new(),
+            level: LevelFilter::Info,
+            stderr: true,
+        })))
+    }
+}
+
+impl LogBuffer {
+    fn push(&self, level: Level, line: String) {
+        let mut state = self.0.lock().unwrap();
+        if state.records.len() == CAPACITY {
+            state.records.pop_front();
+        }
+        state.records.push_back((level, line));
+    }
+
+    // Records up to the level of the pane, oldest first
+    pub fn lines(&self) -> Vec<(Level, String)> {
+        let state = self.0.lock().unwrap();
+        state
+            .records
+            .iter()
+            .filter(|(record, _)| *record <= state.level)
+            .cloned()
+            .collect()
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        self.0.lock().unwrap().level
+    }
+
+    // Switch to the next more verbose level, wrapping around to errors only
+    pub fn next_level(&self) -> LevelFilter {
+        let mut state = self.0.lock().unwrap();
+        let index = LEVELS.iter().position(|level| *level == state.level).unwrap_or(0);
+        state.level = LEVELS[(index + 1) % LEVELS.len()];
+        state.level
+    }
+
+    // Records are written to stderr unless the TUI owns the terminal
+    pub fn set_stderr(&self, stderr: bool) {
+        self.0.lock().unwrap().stderr = stderr;
+    }
+}
+
+struct Logger {
+    buffer: LogBuffer,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{:<5} {}: {}", record.level(), record.target(), record.args());
+        let (level, stderr) = {
+            let state = self.buffer.0.lock().unwrap();
+            (state.level, state.stderr)
+        };
+        if record.level() <= level {
+            if stderr {
+                eprintln!("{line}");
+            }
+            if let Some(file) = &self.file {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs_f64())
+                    .unwrap_or_default();
+                let _ = writeln!(file.lock().unwrap(), "{time:.3} {line}");
+            }
+        }
+        self.buffer.push(record.level(), line);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+// Replaces env_logger for the TUI, the initial level of the pane is taken from RUST_LOG (e.g. `debug`)
+pub fn init(file: Option<&str>) -> anyhow::Result<LogBuffer> {
+    let buffer = LogBuffer::default();
+    if let Some(level) = std::env::var("RUST_LOG").ok().and_then(|level| level.parse().ok()) {
+        buffer.0.lock().unwrap().level = level;
+    }
+    let file = file
+        .map(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed opening log file {path}"))
+        })
+        .transpose()?
+        .map(Mutex::new);
+
+    let logger = Box::leak(Box::new(Logger {
+        buffer: buffer.clone(),
+        file,
+    }));
+    log::set_logger(logger).map_err(|e| anyhow::Error::msg(e.to_string()))?;
+    // Every record is kept, so switching the pane to a more verbose level shows earlier ones
+    log::set_max_level(LevelFilter::Trace);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity() {
+        let logs = LogBuffer::default();
+        for i in 0..CAPACITY + 10 {
+            logs.push(Level::Info, i.to_string());
+        }
+        let lines = logs.lines();
+        assert_eq!(lines.len(), CAPACITY);
+        // Oldest records are dropped first
+        assert_eq!(lines[0].1, "10");
+        assert_eq!(lines[CAPACITY - 1].1, (CAPACITY + 9).to_string());
+    }
+
+    #[test]
+    fn test_level() {
+        let logs = LogBuffer::default();
+        logs.push(Level::Error, "error".to_string());
+        logs.push(Level::Debug, "debug".to_string());
+        logs.push(Level::Info, "info".to_string());
+        let lines = |logs: &LogBuffer| logs.lines().into_iter().map(|(_, line)| line).collect::<Vec<_>>();
+        assert_eq!(lines(&logs), vec!["error", "info"]);
+
+        // Hidden records are kept and shown at a more verbose level
+        assert_eq!(logs.next_level(), LevelFilter::Debug);
+        assert_eq!(lines(&logs), vec!["error", "debug", "info"]);
+        assert_eq!(logs.next_level(), LevelFilter::Trace);
+        assert_eq!(logs.next_level(), LevelFilter::Error);
+        assert_eq!(lines(&logs), vec!["error"]);
+    }
+}
@@ -1,67 +1,395 @@
 // Load config from files
-// TODO: interface to change during runtime
 pub mod config;
+// Reload of the config at runtime
+pub mod reload;
 // TUI for user interface
 pub mod tui;
 // Meta structure that stores mappings
 pub mod mapping;
+// Control socket to change mappings at runtime
+pub mod control;
+// Client commands for the control socket
+pub mod client;
+// Maps and links pinned in bpffs to survive restarts
+pub mod pin;
+// Daemon without the TUI
+pub mod headless;
+// Prometheus metrics endpoint
+pub mod metrics;
+// Editing of mappings in the TUI
+pub mod editor;
+// Rates and their history in the TUI
+pub mod rates;
+// Log pane of the TUI
+pub mod logs;
+// Replacement of attached XDP programs with netlink
+pub mod xdp;
+// Programs attached to the interface, read from the kernel
+pub mod attached;
+// Route netlink requests and attribute parsing shared by xdp and attached
+pub mod netlink;
 
 use std::{
-    fs::File,
+    fs::{self, File},
     io::BufReader,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::fd::AsFd,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::Context;
 use aya::{
     maps::{HashMap, MapData, PerCpuArray},
-    programs::{tc, SchedClassifier, TcAttachType, Xdp, XdpFlags}, EbpfLoader,
+    programs::{links::FdLink, tc, SchedClassifier, TcAttachType, Xdp, XdpFlags}, Ebpf, EbpfLoader,
 };
 use bier_common::bier::{BierHdr, BIER_HEADER_SIZE};
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 #[rustfmt::skip]
-use log::{debug, warn};
-use tokio::signal;
+use log::{debug, info, warn};
 
-use crate::{config::BierMapping, mapping::Mappings, tui::run_tui};
+use crate::{
+    attached::Attached,
+    client::{AddOpt, Show},
+    config::BierMapping,
+    headless::run_headless,
+    logs::LogBuffer,
+    mapping::Mappings,
+    pin::Pins,
+    reload::ConfigWatch,
+    tui::run_tui,
+    xdp::Replaced,
+};
 
 #[derive(Debug, Parser)]
 struct Opt {
+    /// Control socket of the daemon
+    #[clap(long, global = true, default_value = "/run/bier.sock")]
+    socket: String,
+    /// Print responses of the daemon as JSON
+    #[clap(long, global = true)]
+    json: bool,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Attach to the interface and run the daemon with the TUI
+    Run(RunOpt),
+    /// Show the mappings, counters or local BFR-ids of the daemon
+    Show {
+        #[clap(value_enum)]
+        what: Show,
+    },
+    /// Add or update the mapping of a group
+    Add(AddOpt),
+    /// Delete the mapping of a group
+    Del {
+        /// IPv4 or IPv6 multicast group
+        group: IpAddr,
+    },
+    /// Set the local BFR-ids
+    SetLocal {
+        /// BFR-ids of this BFR, none to stop decapsulating
+        bfr_ids: Vec<u32>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct RunOpt {
+    /// Interface to attach the programs to
     #[clap(short, long, default_value = "lo")]
     iface: String,
+    /// Config file with the mappings, reloaded on SIGHUP or change. IPv4 mappings with
+    /// `"mtu_policy": "icmp"` enable accept_local of the interface until exit.
     #[clap(short, long, default_value = "config.json")]
-    config: String
+    config: String,
+    /// Where BIER packets are decapsulated. If not given XDP driver mode is tried first,
+    /// falling back to XDP generic mode and TC.
+    #[clap(long, value_enum)]
+    ingress: Option<IngressMode>,
+    /// Replace an XDP program that is already attached to the interface
+    #[clap(long, conflicts_with = "pin")]
+    xdp_replace: bool,
+    /// Pin maps and links under /sys/fs/bpf/<instance>, a restart reuses them
+    #[clap(long)]
+    pin: Option<String>,
+    /// Run without the TUI until SIGINT or SIGTERM
+    #[clap(long)]
+    headless: bool,
+    /// Counter snapshots of headless mode as JSON lines, `-` for stdout
+    #[clap(long, default_value = "-")]
+    stats: String,
+    /// Seconds between counter snapshots
+    #[clap(long, default_value_t = 10)]
+    stats_interval: u64,
+    /// Mirror the log pane of the TUI to a file
+    #[clap(long)]
+    log_file: Option<String>,
+    /// Serve Prometheus metrics at http://<addr>/metrics, e.g. 127.0.0.1:9464
+    #[clap(long)]
+    metrics: Option<SocketAddr>,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let opt = Opt::parse();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum IngressMode {
+    /// Offloaded to the NIC
+    XdpOffload,
+    /// XDP in the driver
+    #[value(alias = "xdp-driver")]
+    XdpNative,
+    /// XDP on the generic SKB path, works with every driver
+    #[value(alias = "xdp-skb")]
+    XdpGeneric,
+    /// TC ingress, decapsulation and forwarding in one program
+    Tc,
+}
 
-    env_logger::init();
+impl IngressMode {
+    fn name(&self) -> &'static str {
+        match self {
+            IngressMode::XdpOffload => "XDP offload",
+            IngressMode::XdpNative => "XDP driver",
+            IngressMode::XdpGeneric => "XDP generic",
+            IngressMode::Tc => "TC",
+        }
+    }
+}
 
-    let Opt { iface, config } = opt;
+// Modes tried in order if no mode is requested
+const FALLBACK_MODES: [IngressMode; 3] = [IngressMode::XdpNative, IngressMode::XdpGeneric, IngressMode::Tc];
+
+fn attach_tc(
+    ebpf: &mut Ebpf,
+    iface: &str,
+    program: &str,
+    attach_type: TcAttachType,
+    links: Option<&mut Vec<(String, FdLink)>>,
+) -> anyhow::Result<()> {
+    let tc: &mut SchedClassifier = ebpf.program_mut(program).unwrap().try_into()?;
+    tc.load()?;
+    let link = tc.attach(iface, attach_type)?;
+    if let Some(links) = links {
+        links.push((program.to_string(), pin::fd_link(program, tc.take_link(link)?)?));
+    }
+    Ok(())
+}
+
+// With `replace` an attached XDP program is replaced, the replacement is attached with netlink
+// and returned to be detached on exit
+fn attach_xdp(
+    ebpf: &mut Ebpf,
+    iface: &str,
+    flags: XdpFlags,
+    replace: bool,
+    links: Option<&mut Vec<(String, FdLink)>>,
+) -> anyhow::Result<Option<Replaced>> {
+    let xdp: &mut Xdp = ebpf.program_mut("bier_ingress").unwrap().try_into()?;
+    if xdp.fd().is_err() {
+        xdp.load()?;
+    }
+    if replace {
+        let if_index = xdp::if_index(iface).with_context(|| format!("Unknown interface {iface}"))?;
+        if let Some(old) = xdp::attached(if_index)? {
+            xdp::replace(if_index, Some(xdp.fd()?.as_fd()), old.as_fd(), flags)
+                .context("Failed replacing the attached XDP program")?;
+            info!("Replaced the XDP program attached to {iface}");
+            return Ok(Some(Replaced { if_index, flags }));
+        }
+    }
+    let link = xdp.attach(iface, flags)?;
+    if let Some(links) = links {
+        links.push(("bier_ingress".to_string(), pin::fd_link("bier_ingress", xdp.take_link(link)?)?));
+    }
+    Ok(None)
+}
+
+fn attach_mode(
+    ebpf: &mut Ebpf,
+    iface: &str,
+    mode: IngressMode,
+    replace: bool,
+    links: Option<&mut Vec<(String, FdLink)>>,
+) -> anyhow::Result<Option<Replaced>> {
+    match mode {
+        IngressMode::XdpOffload => attach_xdp(ebpf, iface, XdpFlags::HW_MODE, replace, links),
+        IngressMode::XdpNative => attach_xdp(ebpf, iface, XdpFlags::DRV_MODE, replace, links),
+        IngressMode::XdpGeneric => attach_xdp(ebpf, iface, XdpFlags::SKB_MODE, replace, links),
+        IngressMode::Tc => attach_tc(ebpf, iface, "bier_ingress_tc", TcAttachType::Ingress, links).map(|_| None),
+    }
+}
+
+// Attach decapsulation in the requested mode, or in the first of the fallback modes that works
+fn attach_ingress(
+    ebpf: &mut Ebpf,
+    iface: &str,
+    requested: Option<IngressMode>,
+    replace: bool,
+    mut links: Option<&mut Vec<(String, FdLink)>>,
+) -> anyhow::Result<(IngressMode, Option<Replaced>)> {
+    if let Some(mode) = requested {
+        let replaced = attach_mode(ebpf, iface, mode, replace, links)
+            .with_context(|| format!("Failed to attach ingress to {iface} in {} mode", mode.name()))?;
+        info!("Attached ingress to {iface} in {} mode", mode.name());
+        return Ok((mode, replaced));
+    }
+
+    for mode in FALLBACK_MODES {
+        match attach_mode(ebpf, iface, mode, replace, links.as_deref_mut()) {
+            Ok(replaced) => {
+                info!("Attached ingress to {iface} in {} mode", mode.name());
+                return Ok((mode, replaced));
+            }
+            Err(e) => warn!("Failed to attach ingress to {iface} in {} mode, trying the next one: {e:#}", mode.name()),
+        }
+    }
+    Err(anyhow::Error::msg(format!("Failed to attach ingress to {iface} in any mode")))
+}
+
+// accept_local of the interface, the previous value is restored when dropped
+struct AcceptLocal {
+    path: String,
+    previous: Option<String>,
+}
+
+impl AcceptLocal {
+    fn enable(iface: &str) -> anyhow::Result<AcceptLocal> {
+        let path = format!("/proc/sys/net/ipv4/conf/{iface}/accept_local");
+        let previous = fs::read_to_string(&path)?;
+        fs::write(&path, "1")?;
+        Ok(AcceptLocal { path, previous: Some(previous) })
+    }
+
+    // Pinned programs keep returning packet too big after exit
+    fn keep(mut self) {
+        self.previous = None;
+    }
+}
+
+impl Drop for AcceptLocal {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            if let Err(e) = fs::write(&self.path, previous) {
+                warn!("Failed restoring {}: {e}", self.path);
+            }
+        }
+    }
+}
+
+async fn run(opt: RunOpt, socket: &str, logs: LogBuffer) -> anyhow::Result<()> {
+    let RunOpt { iface, config, ingress, xdp_replace, pin, headless, stats, stats_interval, metrics, .. } = opt;
+    let pins = pin.as_deref().map(Pins::new).transpose()?;
 
     // Get config
-    let mut mappings = Mappings::from_file(&config, "bier").context("Failed loading Mappings!")?;
+    let mut mappings =
+        Mappings::from_file(&config, "bier", pins.as_ref()).context("Failed loading Mappings!")?;
 
+    // Attached programs and their mode, exported as metrics
+    let mut attached: Vec<(String, String)> = Vec::new();
+    let mut replaced = None;
+    // Links of freshly attached programs, pinned once the daemon is set up
+    let mut links = pins.as_ref().map(|_| Vec::new());
+    let ingress = match mappings.ebpf.as_mut() {
+        Some(ebpf) => {
+            let _ = tc::qdisc_add_clsact(&iface);
 
-    // --- XDP init, ingress
-    let xdp: &mut Xdp = mappings.ebpf.program_mut("bier_ingress").unwrap().try_into()?;
-    xdp.load()?;
-    xdp.attach(&iface, XdpFlags::default())
-        .context("failed to attach the XDP program with default flags - try changing XdpFlags::default() to XdpFlags::SKB_MODE")?;
+            // --- Ingress, XDP or TC decapsulation
+            let (ingress, xdp) = attach_ingress(ebpf, &iface, ingress, xdp_replace, links.as_mut())?;
+            replaced = xdp;
 
-    // -- TC init, egress
-    let _ = tc::qdisc_add_clsact(&iface);
-    let tc: &mut SchedClassifier = mappings.ebpf.program_mut("bier_egress").unwrap().try_into()?;
-    tc.load()?;
-    tc.attach(&iface, TcAttachType::Egress)?;
+            // -- TC init, egress
+            attach_tc(ebpf, &iface, "bier_egress", TcAttachType::Egress, links.as_mut())?;
+
+            // -- TC init, ingress forwarding, part of the TC ingress program without XDP
+            if ingress != IngressMode::Tc {
+                attach_tc(ebpf, &iface, "bier_forward", TcAttachType::Ingress, links.as_mut())?;
+                attached.push(("bier_ingress".to_string(), ingress.name().to_string()));
+                attached.push(("bier_forward".to_string(), "TC".to_string()));
+            } else {
+                attached.push(("bier_ingress_tc".to_string(), ingress.name().to_string()));
+            }
+            attached.push(("bier_egress".to_string(), "TC".to_string()));
+            ingress.name()
+        }
+        // -- Programs of the previous run are still attached
+        None => {
+            let pins = pins.as_ref().unwrap();
+            info!("Reusing pinned maps in {}", pins.dir().display());
+            attached.extend(pins.links().into_iter().map(|program| (program, "pinned".to_string())));
+            "pinned"
+        }
+    };
 
     // -- Write egress IPMC to BIER map  
     mappings.write_config().context("Failed writing mappings to data plane!")?;
 
+    // -- Accept packet too big for IPv4 senders, see BierConfig::icmp_v4
+    let accept_local = if mappings.icmp_v4() {
+        Some(AcceptLocal::enable(&iface).context("Failed enabling accept_local for ICMP packet too big!")?)
+    } else {
+        None
+    };
+
+    // -- Pin maps and links, a failed start leaves nothing behind to be reused
+    if let (Some(pins), Some(links)) = (pins.as_ref(), links) {
+        mappings.pin(pins, links).context("Failed pinning maps and links!")?;
+    }
+
+    // -- Reload on SIGHUP or file change
+    let watch = ConfigWatch::new(&config)?;
+
+    // -- Control socket and TUI share the mappings
+    let mappings = Arc::new(Mutex::new(mappings));
+    control::spawn(socket, mappings.clone())?;
+    if let Some(addr) = metrics {
+        metrics::spawn(addr, mappings.clone(), Attached::new(&iface, attached))?;
+    }
+
     // ebpf remains alive and loaded here!
-    let _ = run_tui(mappings);
+    if headless {
+        run_headless(mappings.clone(), watch, &stats, Duration::from_secs(stats_interval.max(1))).await?;
+    } else {
+        let _ = run_tui(mappings.clone(), ingress, watch, logs.clone());
+        // Also if the TUI failed before restoring the terminal
+        logs.set_stderr(true);
+    }
 
+    // Pinned programs stay attached, like programs attached with a link they are
+    // otherwise detached on exit
+    if let (Some(replaced), None) = (replaced, pins.as_ref()) {
+        let mappings = mappings.lock().unwrap();
+        if let Some(ebpf) = mappings.ebpf.as_ref() {
+            let xdp: &Xdp = ebpf.program("bier_ingress").unwrap().try_into()?;
+            replaced.detach(xdp.fd()?).context("Failed detaching the XDP program")?;
+        }
+    }
+    if let (Some(accept_local), Some(_)) = (accept_local, pins.as_ref()) {
+        accept_local.keep();
+    }
+
+    let _ = fs::remove_file(socket);
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let Opt { socket, json, command } = Opt::parse();
+
+    // Logs go to the log pane while the TUI owns the terminal
+    let logs = match &command {
+        Command::Run(opt) if !opt.headless => logs::init(opt.log_file.as_deref())?,
+        _ => {
+            env_logger::init();
+            LogBuffer::default()
+        }
+    };
+
+    match command {
+        Command::Run(opt) => run(opt, &socket, logs).await,
+        Command::Show { what } => client::show(&socket, what, json),
+        Command::Add(opt) => client::add(&socket, &opt, json),
+        Command::Del { group } => client::del(&socket, group, json),
+        Command::SetLocal { bfr_ids } => client::set_local(&socket, &bfr_ids, json),
+    }
+}
@@ -1,31 +1,61 @@
-use std::{fs::File, io::BufReader, net::IpAddr, u64};
+use std::{
+    collections::HashSet,
+    fs::File,
+    hash::Hash,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    u64,
+};
 
 use anyhow::Context;
 use aya::{
-    maps::{HashMap, MapData},
-    Ebpf, EbpfLoader,
+    maps::{Array, HashMap, Map, MapData, PerCpuArray, PerCpuHashMap, PerCpuValues, RingBuf},
+    programs::links::FdLink,
+    util::nr_cpus,
+    Ebpf, EbpfLoader, Pod,
+};
+use bier_common::{
+    bier::MAX_BITSTRING_LEN,
+    bift::{BiftEntry, BiftInfo, BiftKey, TeAdjacency},
+    counter::Counter,
+    egress::EgressEntry,
+    reason::Reason,
+    ttl::TtlExpiry,
 };
-use bier_common::bier::BIER_HEADER_SIZE;
 use log::{debug, warn};
 
 use crate::{
-    config::{BierConfig, BierMapping},
-    tui::RowData,
+    config::{local_bitstring, BierConfig, BierMapping, ConfigDiff, SerdeBierHdr},
+    pin::{map_handle, Pins},
+    tui::{RowData, RxRowData},
 };
 
 pub struct Mappings {
     data: BierConfig,
-    v4: HashMap<MapData, u32, [u8; BIER_HEADER_SIZE]>,
-    v6: HashMap<MapData, u128, [u8; BIER_HEADER_SIZE]>,
-    v4_ingress: HashMap<MapData, u32, u64>,
-    v4_egress: HashMap<MapData, u32, u64>,
-    v6_ingress: HashMap<MapData, u128, u64>,
-    v6_egress: HashMap<MapData, u128, u64>,
-    pub ebpf: Ebpf,
+    file: String,
+    local: Array<MapData, [u8; MAX_BITSTRING_LEN]>,
+    v4: HashMap<MapData, u32, EgressEntry>,
+    v6: HashMap<MapData, u128, EgressEntry>,
+    v4_ingress: PerCpuHashMap<MapData, u32, Counter>,
+    v4_egress: PerCpuHashMap<MapData, u32, Counter>,
+    v6_ingress: PerCpuHashMap<MapData, u128, Counter>,
+    v6_egress: PerCpuHashMap<MapData, u128, Counter>,
+    bfir_ingress: PerCpuHashMap<MapData, u16, Counter>,
+    bift_ingress: PerCpuHashMap<MapData, u32, Counter>,
+    bift: HashMap<MapData, BiftKey, BiftEntry>,
+    bift_info: HashMap<MapData, u32, BiftInfo>,
+    bift_te: HashMap<MapData, BiftKey, TeAdjacency>,
+    bierin6_local: HashMap<MapData, u128, u8>,
+    ttl_expired: PerCpuHashMap<MapData, u32, Counter>,
+    ttl_events: RingBuf<MapData>,
+    reasons: PerCpuArray<MapData, u64>,
+    // Not loaded if the maps are reused from pins, the programs of the previous run stay attached
+    pub ebpf: Option<Ebpf>,
+    // Maps of freshly loaded programs, pinned once the programs are attached
+    unpinned: Vec<(String, MapData)>,
 }
 
 impl Mappings {
-    fn load_ebpf(name: &str, local: &[u8; 32]) -> anyhow::Result<Ebpf> {
+    fn load_ebpf(name: &str, forwarding: bool, ttl_notify: bool) -> anyhow::Result<Ebpf> {
         // Bump the memlock rlimit. This is needed for older kernels that don't use the
         // new memcg based accounting, see https://lwn.net/Articles/837122/
         let rlim = libc::rlimit {
@@ -42,7 +72,8 @@ impl Mappings {
         // like to specify the eBPF program at runtime rather than at compile-time, you can
         // reach for `Bpf::load_file` instead.
         let mut ebpf = EbpfLoader::new()
-            .set_global("LOCAL_BFER", local, true)
+            .set_global("BFR_FORWARDING", &(forwarding as u8), true)
+            .set_global("TTL_NOTIFY", &(ttl_notify as u8), true)
             .load(aya::include_bytes_aligned!(concat!(
                 env!("OUT_DIR"),
                 "/bier"
@@ -54,72 +85,356 @@ impl Mappings {
 
         Ok(ebpf)
     }
-    pub fn from_file(file: &str, program: &str) -> anyhow::Result<Mappings> {
-        let data: BierConfig = serde_json::from_reader(BufReader::new(File::open(file)?))?;
+    pub fn from_file(file: &str, program: &str, pins: Option<&Pins>) -> anyhow::Result<Mappings> {
+        let data = BierConfig::from_file(file)?;
 
-        let mut ebpf = Mappings::load_ebpf(program, &data.local)?;
+        let restore = match pins {
+            Some(pins) if pins.restorable()? => Some(pins),
+            _ => None,
+        };
+        let mut ebpf = match restore {
+            Some(_) => None,
+            None => Some(Mappings::load_ebpf(program, data.forwarding(), data.ttl_notify)?),
+        };
+
+        let mut unpinned = Vec::new();
+        let mut take_map = |name: &str| -> anyhow::Result<Map> {
+            match (ebpf.as_mut(), restore) {
+                (Some(ebpf), _) => {
+                    let map = ebpf.take_map(name).with_context(|| format!("Map {name} not found!"))?;
+                    if pins.is_some() {
+                        unpinned.push((name.to_string(), map_handle(&map)?));
+                    }
+                    Ok(map)
+                }
+                (None, Some(pins)) => pins.map(name),
+                (None, None) => unreachable!(),
+            }
+        };
+
+        // Local BFER bits
+        let local: Array<_, [u8; MAX_BITSTRING_LEN]> =
+            Array::try_from(take_map("LOCAL_BFER")?)?;
 
         // Main maps for mapping IP to BIER
-        let v4: HashMap<_, u32, [u8; BIER_HEADER_SIZE]> =
-            HashMap::try_from(ebpf.take_map("IPMC4_TO_BIER").unwrap())?;
-        let v6: HashMap<_, u128, [u8; BIER_HEADER_SIZE]> =
-            HashMap::try_from(ebpf.take_map("IPMC6_TO_BIER").unwrap())?;
+        let v4: HashMap<_, u32, EgressEntry> =
+            HashMap::try_from(take_map("IPMC4_TO_BIER")?)?;
+        let v6: HashMap<_, u128, EgressEntry> =
+            HashMap::try_from(take_map("IPMC6_TO_BIER")?)?;
 
-        // Packet counters based on address
+        // Packet and byte counters based on address, per CPU
         // -- IPv4
-        let v4_ingress: HashMap<_, u32, u64> =
-            HashMap::try_from(ebpf.take_map("v4_INGRESS_COUNTER").unwrap())?;
-        let v4_egress: HashMap<_, u32, u64> =
-            HashMap::try_from(ebpf.take_map("v4_EGRESS_COUNTER").unwrap())?;
+        let v4_ingress: PerCpuHashMap<_, u32, Counter> =
+            PerCpuHashMap::try_from(take_map("v4_INGRESS_COUNTER")?)?;
+        let v4_egress: PerCpuHashMap<_, u32, Counter> =
+            PerCpuHashMap::try_from(take_map("v4_EGRESS_COUNTER")?)?;
         // -- IPv6
-        let v6_ingress: HashMap<_, u128, u64> =
-            HashMap::try_from(ebpf.take_map("v6_INGRESS_COUNTER").unwrap())?;
-        let v6_egress: HashMap<_, u128, u64> =
-            HashMap::try_from(ebpf.take_map("v6_EGRESS_COUNTER").unwrap())?;
+        let v6_ingress: PerCpuHashMap<_, u128, Counter> =
+            PerCpuHashMap::try_from(take_map("v6_INGRESS_COUNTER")?)?;
+        let v6_egress: PerCpuHashMap<_, u128, Counter> =
+            PerCpuHashMap::try_from(take_map("v6_EGRESS_COUNTER")?)?;
+
+        // -- Decapsulated packets by BFIR-id and BIFT-id
+        let bfir_ingress: PerCpuHashMap<_, u16, Counter> =
+            PerCpuHashMap::try_from(take_map("BFIR_INGRESS_COUNTER")?)?;
+        let bift_ingress: PerCpuHashMap<_, u32, Counter> =
+            PerCpuHashMap::try_from(take_map("BIFT_INGRESS_COUNTER")?)?;
+
+        // Bit Index Forwarding Table for transit BFR
+        let bift: HashMap<_, BiftKey, BiftEntry> =
+            HashMap::try_from(take_map("BIFT")?)?;
+        // BIER/BIER-TE mode and adjacencies per BIFT-id
+        let bift_info: HashMap<_, u32, BiftInfo> =
+            HashMap::try_from(take_map("BIFT_INFO")?)?;
+        let bift_te: HashMap<_, BiftKey, TeAdjacency> =
+            HashMap::try_from(take_map("BIFT_TE")?)?;
+
+        // Local addresses of received BIERin6 packets
+        let bierin6_local: HashMap<_, u128, u8> =
+            HashMap::try_from(take_map("BIERIN6_LOCAL")?)?;
+
+        // Packets with expired TTL by BIFT-id and notifications about them
+        let ttl_expired: PerCpuHashMap<_, u32, Counter> =
+            PerCpuHashMap::try_from(take_map("TTL_EXPIRED_COUNTER")?)?;
+        let ttl_events = RingBuf::try_from(take_map("TTL_EVENTS")?)?;
+
+        // Dropped and unmodified passed packets by reason
+        let reasons: PerCpuArray<_, u64> =
+            PerCpuArray::try_from(take_map("REASON_COUNTER")?)?;
 
         Ok(Mappings {
             data,
+            file: file.to_string(),
+            local,
             v4,
             v6,
             v4_ingress,
             v4_egress,
             v6_ingress,
             v6_egress,
+            bfir_ingress,
+            bift_ingress,
+            bift,
+            bift_info,
+            bift_te,
+            bierin6_local,
+            ttl_expired,
+            ttl_events,
+            reasons,
             ebpf,
+            unpinned,
         })
     }
 
-    pub fn write_config(&mut self) -> anyhow::Result<()> {
-        for (addr, hdr) in self.data.entries.iter() {
-            match addr {
-                IpAddr::V4(data) => {
-                    let num: u32 = u32::from_be_bytes(data.octets());
-                    self.v4
-                        .insert(num, hdr.to_bier_hdr()?.into_bytes(), 0)
-                        .context("Failed insertion of IPv4 HashMap entry!")?;
-
-                    // Initialize counter!
-                    self.v4_egress.insert(num, 0, 0)?;
+    // Pin the maps and the links of the attached programs
+    pub fn pin(&mut self, pins: &Pins, links: Vec<(String, FdLink)>) -> anyhow::Result<()> {
+        pins.pin(std::mem::take(&mut self.unpinned), links)
+    }
+
+    // Insert or update a mapping, counters are only reset for new mappings
+    fn insert_entry(&mut self, addr: IpAddr, entry: EgressEntry, new: bool) -> anyhow::Result<()> {
+        let nr_cpus = nr_cpus().map_err(|(_, e)| e)?;
+        let zero = || PerCpuValues::try_from(vec![Counter::default(); nr_cpus]);
+
+        match addr {
+            IpAddr::V4(data) => {
+                let num: u32 = u32::from_be_bytes(data.octets());
+                self.v4
+                    .insert(num, entry, 0)
+                    .context("Failed insertion of IPv4 HashMap entry!")?;
+
+                // Initialize counter!
+                if new {
+                    self.v4_egress.insert(num, zero()?, 0)?;
                 }
-                IpAddr::V6(data) => {
-                    let num: u128 = u128::from_be_bytes(data.octets());
-                    self.v6
-                        .insert(num, hdr.to_bier_hdr()?.into_bytes(), 0)
-                        .context("Failed insertion of IPv6 HashMap entry!")?;
+            }
+            IpAddr::V6(data) => {
+                let num: u128 = u128::from_be_bytes(data.octets());
+                self.v6
+                    .insert(num, entry, 0)
+                    .context("Failed insertion of IPv6 HashMap entry!")?;
 
-                    self.v6_egress.insert(num, 0, 0)?;
+                if new {
+                    self.v6_egress.insert(num, zero()?, 0)?;
                 }
             }
         }
         Ok(())
     }
 
+    fn remove_entry(&mut self, addr: IpAddr) -> anyhow::Result<()> {
+        match addr {
+            IpAddr::V4(data) => {
+                let num: u32 = u32::from_be_bytes(data.octets());
+                self.v4.remove(&num).context("Failed removal of IPv4 HashMap entry!")?;
+                let _ = self.v4_egress.remove(&num);
+            }
+            IpAddr::V6(data) => {
+                let num: u128 = u128::from_be_bytes(data.octets());
+                self.v6.remove(&num).context("Failed removal of IPv6 HashMap entry!")?;
+                let _ = self.v6_egress.remove(&num);
+            }
+        }
+        Ok(())
+    }
+
+    // Egress entries of the given mappings, converted before any map is touched
+    fn egress_entries(&self, addrs: &[IpAddr], config: &BierConfig) -> anyhow::Result<Vec<(IpAddr, EgressEntry)>> {
+        addrs
+            .iter()
+            .map(|addr| {
+                let entry = config.entries[addr]
+                    .to_egress_entry()
+                    .with_context(|| format!("Invalid mapping for {addr}"))?;
+                Ok((*addr, entry))
+            })
+            .collect()
+    }
+
+    // Groups with a mapping in the data plane
+    fn mapped_groups(&self) -> Vec<IpAddr> {
+        self.v4
+            .keys()
+            .filter_map(Result::ok)
+            .map(|addr| IpAddr::V4(Ipv4Addr::from(addr)))
+            .chain(
+                self.v6
+                    .keys()
+                    .filter_map(Result::ok)
+                    .map(|addr| IpAddr::V6(Ipv6Addr::from(addr))),
+            )
+            .collect()
+    }
+
+    fn has_egress_counter(&self, addr: IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(data) => self.v4_egress.get(&u32::from_be_bytes(data.octets()), 0).is_ok(),
+            IpAddr::V6(data) => self.v6_egress.get(&u128::from_be_bytes(data.octets()), 0).is_ok(),
+        }
+    }
+
+    // Maps reused from pins hold the config of the previous run, entries that are no longer
+    // configured are removed and counters of the remaining groups are kept
+    pub fn write_config(&mut self) -> anyhow::Result<()> {
+        self.local
+            .set(0, self.data.local_bfer()?, 0)
+            .context("Failed writing local BFER bits!")?;
+
+        let addrs: Vec<IpAddr> = self.data.entries.keys().copied().collect();
+        for addr in self.mapped_groups() {
+            if !self.data.entries.contains_key(&addr) {
+                self.remove_entry(addr)?;
+            }
+        }
+        for (addr, entry) in self.egress_entries(&addrs, &self.data)? {
+            let new = !self.has_egress_counter(addr);
+            self.insert_entry(addr, entry, new)?;
+        }
+
+        let mut bift = Vec::new();
+        for neighbor in self.data.bift.iter() {
+            bift.extend(
+                neighbor
+                    .to_bift_entries()
+                    .with_context(|| format!("Invalid BIFT neighbor on {}", neighbor.iface))?,
+            );
+        }
+        replace_entries(&mut self.bift, bift).context("Failed insertion of BIFT entry!")?;
+
+        let mut bift_info = Vec::new();
+        let mut bift_te = Vec::new();
+        for (bift_id, settings) in self.data.bift_ids.iter() {
+            let info = settings
+                .to_bift_info()
+                .with_context(|| format!("Invalid settings for BIFT-id {bift_id}"))?;
+            bift_info.push((*bift_id, info));
+
+            bift_te.extend(
+                settings
+                    .to_te_adjacencies(*bift_id)
+                    .with_context(|| format!("Invalid BIER-TE adjacency for BIFT-id {bift_id}"))?,
+            );
+        }
+        replace_entries(&mut self.bift_info, bift_info).context("Failed insertion of BIFT-id settings!")?;
+        replace_entries(&mut self.bift_te, bift_te).context("Failed insertion of BIER-TE adjacency!")?;
+
+        let bierin6_local = self
+            .data
+            .bierin6_local
+            .iter()
+            .map(|addr| (u128::from_be_bytes(addr.octets()), 1))
+            .collect();
+        replace_entries(&mut self.bierin6_local, bierin6_local).context("Failed insertion of local BIERin6 address!")?;
+        Ok(())
+    }
+
+    // Reload the config file and apply the changed mappings, programs stay attached.
+    // A config with invalid mappings is rejected as a whole.
+    pub fn reload(&mut self) -> anyhow::Result<ConfigDiff> {
+        let data = BierConfig::from_file(&self.file)?;
+        let diff = ConfigDiff::new(&self.data, &data);
+
+        let added = self.egress_entries(&diff.added, &data)?;
+        let updated = self.egress_entries(&diff.updated, &data)?;
+        if diff.local {
+            self.set_local(data.local.clone())?;
+        }
+        for addr in diff.removed.iter() {
+            self.remove_entry(*addr)?;
+        }
+        for (addr, entry) in added {
+            self.insert_entry(addr, entry, true)?;
+        }
+        for (addr, entry) in updated {
+            self.insert_entry(addr, entry, false)?;
+        }
+
+        self.data.entries = data.entries;
+        Ok(diff)
+    }
+
+    // Write the current mappings and local bits back to the config file
+    pub fn save(&self) -> anyhow::Result<()> {
+        let file = File::create(&self.file).with_context(|| format!("Failed writing {}", self.file))?;
+        serde_json::to_writer_pretty(file, &self.data)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &BierMapping {
+        &self.data.entries
+    }
+
+    // Add or update the mapping of a group
+    pub fn set_mapping(&mut self, addr: IpAddr, hdr: SerdeBierHdr) -> anyhow::Result<()> {
+        let entry = hdr
+            .to_egress_entry()
+            .with_context(|| format!("Invalid mapping for {addr}"))?;
+        let new = !self.data.entries.contains_key(&addr);
+        self.insert_entry(addr, entry, new)?;
+        self.data.entries.insert(addr, hdr);
+        Ok(())
+    }
+
+    pub fn remove_mapping(&mut self, addr: IpAddr) -> anyhow::Result<()> {
+        if !self.data.entries.contains_key(&addr) {
+            return Err(anyhow::Error::msg(format!("No mapping for {addr}")));
+        }
+        self.remove_entry(addr)?;
+        self.data.entries.remove(&addr);
+        Ok(())
+    }
+
+    pub fn set_local(&mut self, local: Vec<u8>) -> anyhow::Result<()> {
+        self.local
+            .set(0, local_bitstring(&local)?, 0)
+            .context("Failed writing local BFER bits!")?;
+        self.data.local = local;
+        Ok(())
+    }
+
+    // Zero the counters of all mappings, counters created by the data plane are removed
+    pub fn reset_counters(&mut self) -> anyhow::Result<()> {
+        let nr_cpus = nr_cpus().map_err(|(_, e)| e)?;
+
+        zero_counters(&mut self.v4_egress, nr_cpus)?;
+        zero_counters(&mut self.v6_egress, nr_cpus)?;
+        clear_counters(&mut self.v4_ingress)?;
+        clear_counters(&mut self.v6_ingress)?;
+        clear_counters(&mut self.bfir_ingress)?;
+        clear_counters(&mut self.bift_ingress)?;
+        clear_counters(&mut self.ttl_expired)?;
+
+        for reason in Reason::ALL.iter() {
+            self.reasons
+                .set(*reason as u32, PerCpuValues::try_from(vec![0u64; nr_cpus])?, 0)?;
+        }
+        Ok(())
+    }
+
+    // Egress counters of all mappings, summed over all CPUs
+    pub fn get_egress_counters(&self) -> Vec<(IpAddr, Counter)> {
+        let mut counters: Vec<(IpAddr, Counter)> = sum_counters(&self.v4_egress)
+            .into_iter()
+            .map(|(addr, counter)| (IpAddr::V4(Ipv4Addr::from(addr)), counter))
+            .chain(
+                sum_counters(&self.v6_egress)
+                    .into_iter()
+                    .map(|(addr, counter)| (IpAddr::V6(Ipv6Addr::from(addr)), counter)),
+            )
+            .collect();
+        counters.sort_by_key(|(addr, _)| *addr);
+        counters
+    }
+
     pub fn get_data(&self) -> Vec<RowData> {
         let mut data: Vec<RowData> = Vec::new();
 
+        let received = Counter::sum(sum_counters(&self.bift_ingress).iter().map(|(_, counter)| counter));
         data.push(RowData {
             ipmc_group: "local BFER".to_string(),
-            packet_count: 0,
+            packet_count: received.packets,
+            byte_count: received.bytes,
+            bits: self.data.local.len() * 8,
             bitstring: self
                 .data
                 .local
@@ -129,6 +444,16 @@ impl Mappings {
                 .join("."),
         });
 
+        for (bift_id, counter) in self.get_ttl_expired() {
+            data.push(RowData {
+                ipmc_group: format!("TTL expired {bift_id}"),
+                packet_count: counter.packets,
+                byte_count: counter.bytes,
+                bits: 0,
+                bitstring: String::new(),
+            });
+        }
+
         for (addr, hdr) in self.data.entries.iter() {
             let addr_str = addr.to_string();
 
@@ -142,23 +467,28 @@ impl Mappings {
                     .collect::<Vec<String>>()
                     .join(".");
 
-                let packet_count = match addr {
+                // Values of all CPUs are summed up
+                let counter = match addr {
                     IpAddr::V4(v4_addr) => {
                         let num: u32 = u32::from_be_bytes(v4_addr.octets());
-                        let count: u64 = self.v4_egress.get(&num, 0).unwrap_or(u64::MAX);
-                        count
+                        self.v4_egress.get(&num, 0).map(|values| Counter::sum(values.iter()))
                     }
                     IpAddr::V6(v6_addr) => {
                         let num: u128 = u128::from_be_bytes(v6_addr.octets());
-                        let count: u64 = self.v6_egress.get(&num, 0).unwrap_or(u64::MAX);
-                        count
+                        self.v6_egress.get(&num, 0).map(|values| Counter::sum(values.iter()))
                     }
-                };
+                }
+                .unwrap_or(Counter {
+                    packets: u64::MAX,
+                    bytes: u64::MAX,
+                });
 
                 data.push(RowData {
                     ipmc_group: addr_str,
-                    packet_count,
-                    bitstring: bs, // 256 bits
+                    packet_count: counter.packets,
+                    byte_count: counter.bytes,
+                    bits: hdr.bitstring_len() * 8,
+                    bitstring: bs,
                 });
             }
         }
@@ -174,7 +504,128 @@ impl Mappings {
         }
     }
 
-    pub fn get_local(&self) -> [u8; 32] {
-        self.data.local
+    // Receive side counters of decapsulated packets
+    pub fn get_rx_data(&self) -> Vec<RxRowData> {
+        let mut data: Vec<RxRowData> = Vec::new();
+
+        let mut groups: Vec<(IpAddr, Counter)> = sum_counters(&self.v4_ingress)
+            .into_iter()
+            .map(|(addr, counter)| (IpAddr::V4(Ipv4Addr::from(addr)), counter))
+            .chain(
+                sum_counters(&self.v6_ingress)
+                    .into_iter()
+                    .map(|(addr, counter)| (IpAddr::V6(Ipv6Addr::from(addr)), counter)),
+            )
+            .collect();
+        groups.sort_by_key(|(addr, _)| *addr);
+        data.extend(groups.into_iter().map(|(addr, counter)| RxRowData {
+            kind: "IPMC-Group",
+            key: addr.to_string(),
+            packet_count: counter.packets,
+            byte_count: counter.bytes,
+        }));
+
+        data.extend(sum_counters(&self.bfir_ingress).into_iter().map(|(bfir_id, counter)| RxRowData {
+            kind: "BFIR-id",
+            key: bfir_id.to_string(),
+            packet_count: counter.packets,
+            byte_count: counter.bytes,
+        }));
+
+        data.extend(sum_counters(&self.bift_ingress).into_iter().map(|(bift_id, counter)| RxRowData {
+            kind: "BIFT-id",
+            key: bift_id.to_string(),
+            packet_count: counter.packets,
+            byte_count: counter.bytes,
+        }));
+
+        data
+    }
+
+    // Packets with expired TTL by BIFT-id
+    pub fn get_ttl_expired(&self) -> Vec<(u32, Counter)> {
+        sum_counters(&self.ttl_expired)
+    }
+
+    // Packets dropped or passed unmodified by reason, summed over all CPUs
+    pub fn get_reasons(&self) -> Vec<(Reason, u64)> {
+        Reason::ALL
+            .iter()
+            .map(|reason| {
+                let count = self
+                    .reasons
+                    .get(&(*reason as u32), 0)
+                    .map(|values| values.iter().sum())
+                    .unwrap_or(0);
+                (*reason, count)
+            })
+            .collect()
+    }
+
+    // Notifications received since the last call, empty if ttl_notify is not set
+    pub fn poll_ttl_events(&mut self) -> Vec<TtlExpiry> {
+        let mut events = Vec::new();
+        while let Some(item) = self.ttl_events.next() {
+            if item.len() == std::mem::size_of::<TtlExpiry>() {
+                events.push(unsafe { std::ptr::read_unaligned(item.as_ptr() as *const TtlExpiry) });
+            }
+        }
+        events
+    }
+
+    pub fn icmp_v4(&self) -> bool {
+        self.data.icmp_v4()
+    }
+
+    pub fn get_local(&self) -> &[u8] {
+        &self.data.local
+    }
+}
+
+// Sum of the per CPU counters of all keys, sorted by key
+fn sum_counters<K: Pod + Ord>(map: &PerCpuHashMap<MapData, K, Counter>) -> Vec<(K, Counter)> {
+    let mut counters: Vec<(K, Counter)> = map
+        .iter()
+        .filter_map(Result::ok)
+        .map(|(key, values)| (key, Counter::sum(values.iter())))
+        .collect();
+    counters.sort_by_key(|(key, _)| *key);
+    counters
+}
+
+// Insert all entries and remove keys that are not part of them
+fn replace_entries<K: Pod + Eq + Hash, V: Pod>(
+    map: &mut HashMap<MapData, K, V>,
+    entries: Vec<(K, V)>,
+) -> anyhow::Result<()> {
+    let keys: HashSet<K> = entries.iter().map(|(key, _)| *key).collect();
+    for (key, value) in entries {
+        map.insert(key, value, 0)?;
+    }
+
+    let stale = map
+        .keys()
+        .filter_map(Result::ok)
+        .filter(|key| !keys.contains(key))
+        .collect::<Vec<K>>();
+    for key in stale {
+        map.remove(&key)?;
+    }
+    Ok(())
+}
+
+fn zero_counters<K: Pod>(map: &mut PerCpuHashMap<MapData, K, Counter>, nr_cpus: usize) -> anyhow::Result<()> {
+    let keys = map.keys().collect::<Result<Vec<K>, _>>()?;
+    for key in keys {
+        map.insert(key, PerCpuValues::try_from(vec![Counter::default(); nr_cpus])?, 0)?;
+    }
+    Ok(())
+}
+
+fn clear_counters<K: Pod>(map: &mut PerCpuHashMap<MapData, K, Counter>) -> anyhow::Result<()> {
+    let keys = map.keys().collect::<Result<Vec<K>, _>>()?;
+    for key in keys {
+        map.remove(&key)?;
     }
+    Ok(())
 }
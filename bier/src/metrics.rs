@@ -0,0 +1,230 @@
+// Prometheus metrics of the daemon in text format, served at http://<addr>/metrics
+//
+//     bier_egress_packets_total{group="239.1.1.1",bift_id="100003",bfir_id="1"} 42
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::warn;
+
+use crate::{attached::Attached, config::SerdeBierHdr, mapping::Mappings};
+
+// Scrapers that stop sending are dropped instead of holding their thread
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    // Labels and value
+    samples: Vec<(String, u64)>,
+}
+
+impl Family {
+    fn counter(name: &'static str, help: &'static str) -> Family {
+        Family {
+            name,
+            help,
+            kind: "counter",
+            samples: Vec::new(),
+        }
+    }
+
+    fn gauge(name: &'static str, help: &'static str) -> Family {
+        Family {
+            name,
+            help,
+            kind: "gauge",
+            samples: Vec::new(),
+        }
+    }
+}
+
+// Labels of an egress counter, a group without mapping keeps its series with empty ids
+fn egress_labels(group: IpAddr, hdr: Option<&SerdeBierHdr>) -> String {
+    let (bift_id, bfir_id) = hdr
+        .map(|hdr| (hdr.bift_id.to_string(), hdr.bfir_id.to_string()))
+        .unwrap_or_default();
+    format!("group=\"{group}\",bift_id=\"{bift_id}\",bfir_id=\"{bfir_id}\"")
+}
+
+// `attached` holds the programs, their attach mode and whether they are attached
+fn programs(attached: &[(String, String, bool)]) -> Family {
+    let mut programs = Family::gauge("bier_program_attached", "Attached programs by attach mode");
+    for (program, mode, live) in attached {
+        programs.samples.push((format!("program=\"{program}\",mode=\"{mode}\""), *live as u64));
+    }
+    programs
+}
+
+// Text exposition format
+fn text(families: impl IntoIterator<Item = Family>) -> String {
+    let mut out = String::new();
+    for family in families {
+        let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+        let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind);
+        for (labels, value) in family.samples {
+            let _ = writeln!(out, "{}{{{labels}}} {value}", family.name);
+        }
+    }
+    out
+}
+
+fn render(mappings: &Mappings, attached: &[(String, String, bool)]) -> String {
+    let mut egress_packets = Family::counter("bier_egress_packets_total", "Packets encapsulated by group");
+    let mut egress_bytes = Family::counter("bier_egress_bytes_total", "Bytes encapsulated by group");
+    for (group, counter) in mappings.get_egress_counters() {
+        let labels = egress_labels(group, mappings.entries().get(&group));
+        egress_packets.samples.push((labels.clone(), counter.packets));
+        egress_bytes.samples.push((labels, counter.bytes));
+    }
+
+    let mut rx_group_packets = Family::counter("bier_ingress_packets_total", "Packets decapsulated by group");
+    let mut rx_group_bytes = Family::counter("bier_ingress_bytes_total", "Bytes decapsulated by group");
+    let mut rx_bfir_packets = Family::counter("bier_ingress_bfir_packets_total", "Packets decapsulated by BFIR-id");
+    let mut rx_bfir_bytes = Family::counter("bier_ingress_bfir_bytes_total", "Bytes decapsulated by BFIR-id");
+    let mut rx_bift_packets = Family::counter("bier_ingress_bift_packets_total", "Packets decapsulated by BIFT-id");
+    let mut rx_bift_bytes = Family::counter("bier_ingress_bift_bytes_total", "Bytes decapsulated by BIFT-id");
+    for rx in mappings.get_rx_data() {
+        let (packets, bytes, label) = match rx.kind {
+            "BFIR-id" => (&mut rx_bfir_packets, &mut rx_bfir_bytes, "bfir_id"),
+            "BIFT-id" => (&mut rx_bift_packets, &mut rx_bift_bytes, "bift_id"),
+            _ => (&mut rx_group_packets, &mut rx_group_bytes, "group"),
+        };
+        let labels = format!("{label}=\"{}\"", rx.key);
+        packets.samples.push((labels.clone(), rx.packet_count));
+        bytes.samples.push((labels, rx.byte_count));
+    }
+
+    let mut ttl_packets = Family::counter("bier_ttl_expired_packets_total", "Packets with expired TTL by BIFT-id");
+    for (bift_id, counter) in mappings.get_ttl_expired() {
+        ttl_packets.samples.push((format!("bift_id=\"{bift_id}\""), counter.packets));
+    }
+
+    let mut reasons = Family::counter(
+        "bier_reason_packets_total",
+        "Packets dropped or passed unmodified by reason",
+    );
+    for (reason, packets) in mappings.get_reasons() {
+        let action = if reason.is_drop() { "drop" } else { "pass" };
+        reasons
+            .samples
+            .push((format!("reason=\"{}\",action=\"{action}\"", reason.name()), packets));
+    }
+
+    text([
+        egress_packets,
+        egress_bytes,
+        rx_group_packets,
+        rx_group_bytes,
+        rx_bfir_packets,
+        rx_bfir_bytes,
+        rx_bift_packets,
+        rx_bift_bytes,
+        ttl_packets,
+        reasons,
+        programs(attached),
+    ])
+}
+
+fn serve_client(stream: TcpStream, mappings: &Mutex<Mappings>, attached: &Attached) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line.trim() != "" {
+        line.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" {
+        // Attach state is read before locking, the netlink queries do not need the mappings
+        let attached = attached.state();
+        ("200 OK", render(&mappings.lock().unwrap(), &attached))
+    } else {
+        ("404 Not Found", "Not found, metrics are served at /metrics\n".to_string())
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+// Serve metrics at `addr` in the background, one thread per scrape
+pub fn spawn(addr: SocketAddr, mappings: Arc<Mutex<Mappings>>, attached: Attached) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).with_context(|| format!("Failed binding metrics endpoint {addr}"))?;
+    let attached = Arc::new(attached);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let mappings = mappings.clone();
+                    let attached = attached.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_client(stream, &mappings, &attached) {
+                            warn!("Metrics client failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Metrics endpoint failed: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let mut packets = Family::counter("bier_egress_packets_total", "Packets encapsulated by group");
+        packets.samples.push(("group=\"239.1.1.1\"".to_string(), 42));
+        packets.samples.push(("group=\"ff3e::1\"".to_string(), 0));
+        let empty = Family::counter("bier_ttl_expired_packets_total", "Packets with expired TTL by BIFT-id");
+
+        assert_eq!(
+            text([packets, empty]),
+            "# HELP bier_egress_packets_total Packets encapsulated by group\n\
+             # TYPE bier_egress_packets_total counter\n\
+             bier_egress_packets_total{group=\"239.1.1.1\"} 42\n\
+             bier_egress_packets_total{group=\"ff3e::1\"} 0\n\
+             # HELP bier_ttl_expired_packets_total Packets with expired TTL by BIFT-id\n\
+             # TYPE bier_ttl_expired_packets_total counter\n"
+        );
+    }
+
+    #[test]
+    fn test_egress_labels() {
+        let group: IpAddr = "239.1.1.1".parse().unwrap();
+        let mut hdr = SerdeBierHdr::new(100, 1, 4);
+        hdr.bfir_id = 3;
+        assert_eq!(egress_labels(group, Some(&hdr)), "group=\"239.1.1.1\",bift_id=\"100\",bfir_id=\"3\"");
+        assert_eq!(egress_labels(group, None), "group=\"239.1.1.1\",bift_id=\"\",bfir_id=\"\"");
+    }
+
+    #[test]
+    fn test_programs() {
+        let attached = vec![
+            ("bier_ingress".to_string(), "XDP driver".to_string(), true),
+            ("bier_egress".to_string(), "TC".to_string(), false),
+        ];
+        let text = text([programs(&attached)]);
+        assert!(text.contains("# TYPE bier_program_attached gauge\n"));
+        assert!(text.contains("bier_program_attached{program=\"bier_ingress\",mode=\"XDP driver\"} 1\n"));
+        assert!(text.contains("bier_program_attached{program=\"bier_egress\",mode=\"TC\"} 0\n"));
+    }
+}
@@ -0,0 +1,165 @@
+// Route netlink requests for the XDP replacement and the attach state of the programs.
+// Only what these need: a request with its response, dumps, and attributes as (type, payload).
+
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+const NLMSG_HDR_LEN: usize = 16;
+const NLA_HDR_LEN: usize = 4;
+const RECV_LEN: usize = 32 * 1024;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub fn attr(kind: u16, payload: &[u8]) -> Vec<u8> {
+    let len = NLA_HDR_LEN + payload.len();
+    let mut attr = Vec::with_capacity(align(len));
+    attr.extend_from_slice(&(len as u16).to_ne_bytes());
+    attr.extend_from_slice(&kind.to_ne_bytes());
+    attr.extend_from_slice(payload);
+    attr.resize(align(len), 0);
+    attr
+}
+
+// Attributes as (type, payload), parsing stops at the first malformed one
+pub fn attrs(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    while data.len() >= NLA_HDR_LEN {
+        let len = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        let kind = u16::from_ne_bytes([data[2], data[3]]) & libc::NLA_TYPE_MASK as u16;
+        if len < NLA_HDR_LEN || len > data.len() {
+            break;
+        }
+        attrs.push((kind, &data[NLA_HDR_LEN..len]));
+        data = &data[align(len).min(data.len())..];
+    }
+    attrs
+}
+
+fn message(msg_type: u16, flags: u16, body: &[u8]) -> Vec<u8> {
+    let len = NLMSG_HDR_LEN + body.len();
+    let mut msg = Vec::with_capacity(len);
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&msg_type.to_ne_bytes());
+    msg.extend_from_slice(&(libc::NLM_F_REQUEST as u16 | flags).to_ne_bytes());
+    msg.extend_from_slice(&1u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(body);
+    msg
+}
+
+// Messages of a received buffer as (type, payload)
+fn messages(mut data: &[u8]) -> io::Result<Vec<(u16, &[u8])>> {
+    let mut messages = Vec::new();
+    while data.len() >= NLMSG_HDR_LEN {
+        let len = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        if len < NLMSG_HDR_LEN || len > data.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated netlink response"));
+        }
+        messages.push((u16::from_ne_bytes([data[4], data[5]]), &data[NLMSG_HDR_LEN..len]));
+        data = &data[align(len).min(data.len())..];
+    }
+    Ok(messages)
+}
+
+// Error code of an NLMSG_ERROR, 0 acknowledges the request
+fn error(payload: &[u8]) -> io::Result<()> {
+    match payload.get(0..4).map_or(0, |code| i32::from_ne_bytes(code.try_into().unwrap())) {
+        0 => Ok(()),
+        code => Err(io::Error::from_raw_os_error(-code)),
+    }
+}
+
+fn send(msg: &[u8]) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::send(sock.as_raw_fd(), msg.as_ptr().cast(), msg.len(), 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sock)
+}
+
+fn recv<'a>(sock: &OwnedFd, buf: &'a mut [u8]) -> io::Result<&'a [u8]> {
+    let len = unsafe { libc::recv(sock.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(&buf[..len as usize])
+}
+
+// Request with `body` after the header, returns the payload of the response
+pub fn request(msg_type: u16, flags: u16, body: &[u8]) -> io::Result<Vec<u8>> {
+    let sock = send(&message(msg_type, flags, body))?;
+    let mut buf = vec![0u8; RECV_LEN];
+    let data = recv(&sock, &mut buf)?;
+    let Some((msg_type, payload)) = messages(data)?.into_iter().next() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short netlink response"));
+    };
+    if msg_type == libc::NLMSG_ERROR as u16 {
+        error(payload)?;
+    }
+    Ok(payload.to_vec())
+}
+
+// Dump request with `body` after the header, returns the payloads of all responses
+pub fn dump(msg_type: u16, body: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let sock = send(&message(msg_type, libc::NLM_F_DUMP as u16, body))?;
+    let mut payloads = Vec::new();
+    let mut buf = vec![0u8; RECV_LEN];
+    loop {
+        for (msg_type, payload) in messages(recv(&sock, &mut buf)?)? {
+            match msg_type as i32 {
+                libc::NLMSG_DONE => return Ok(payloads),
+                libc::NLMSG_ERROR => error(payload)?,
+                _ => payloads.push(payload.to_vec()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attrs() {
+        // Payloads are padded to 4 bytes, the nested flag is not part of the type
+        let mut data = attr(1, b"bpf\0");
+        data.extend(attr(2 | libc::NLA_F_NESTED as u16, &attr(7, b"bier_egress\0")));
+        data.extend(attr(3, &[1, 2, 3, 4, 5]));
+        assert_eq!(data.len(), 8 + 20 + 12);
+
+        let parsed = attrs(&data);
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0], (1, &b"bpf\0"[..]));
+        assert_eq!(parsed[1].0, 2);
+        assert_eq!(attrs(parsed[1].1), vec![(7, &b"bier_egress\0"[..])]);
+        assert_eq!(parsed[2], (3, &[1, 2, 3, 4, 5][..]));
+
+        // A length beyond the data ends the attributes
+        let mut truncated = attr(1, &[0; 8]);
+        truncated.extend(attr(2, &[0; 8]));
+        truncated.truncate(20);
+        assert_eq!(attrs(&truncated), vec![(1, &[0u8; 8][..])]);
+        assert!(attrs(&[4, 0]).is_empty());
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut data = message(libc::RTM_NEWLINK, 0, &[1, 2, 3]);
+        data.resize(20, 0);
+        data.extend(message(libc::NLMSG_DONE as u16, 0, &[]));
+        let parsed = messages(&data).unwrap();
+        assert_eq!(parsed, vec![(libc::RTM_NEWLINK, &[1, 2, 3][..]), (libc::NLMSG_DONE as u16, &[][..])]);
+
+        assert!(messages(&data[..18]).is_err());
+        assert!(error(&(-libc::ENODEV).to_ne_bytes()).is_err());
+        assert!(error(&0i32.to_ne_bytes()).is_ok());
+    }
+}
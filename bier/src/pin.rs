@@ -0,0 +1,180 @@
+// Maps and program links pinned in bpffs, the data plane keeps forwarding with its state
+// while the daemon is restarted or upgraded.
+//
+//     /sys/fs/bpf/<instance>/maps/<map>
+//     /sys/fs/bpf/<instance>/links/<program>
+//     /sys/fs/bpf/<instance>/layout
+//
+// Everything is pinned once all programs are attached, the layout of the map values is
+// written last. Pins without it are left over from a failed start and removed.
+
+use std::{
+    fs,
+    mem::size_of,
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use aya::{
+    maps::{Map, MapData, MapType},
+    programs::links::{FdLink, LinkError, PinnedLink},
+    util::KernelVersion,
+};
+use bier_common::{
+    bier::{BierHdr, MAX_BITSTRING_LEN},
+    bift::{BiftEntry, BiftInfo, BiftKey, TeAdjacency},
+    counter::Counter,
+    egress::EgressEntry,
+    reason::Reason,
+    ttl::TtlExpiry,
+};
+use log::warn;
+
+pub const BPFFS: &str = "/sys/fs/bpf";
+
+// Bumped when maps are added or removed
+const VERSION: u32 = 1;
+
+// Sizes of the keys and values of the pinned maps, maps of another layout are not reused
+pub fn layout() -> String {
+    format!(
+        "version={VERSION} bier_hdr={} egress={} counter={} bift_key={} bift_entry={} bift_info={} te_adjacency={} ttl_expiry={} bitstring={} reasons={}",
+        size_of::<BierHdr>(),
+        size_of::<EgressEntry>(),
+        size_of::<Counter>(),
+        size_of::<BiftKey>(),
+        size_of::<BiftEntry>(),
+        size_of::<BiftInfo>(),
+        size_of::<TeAdjacency>(),
+        size_of::<TtlExpiry>(),
+        MAX_BITSTRING_LEN,
+        Reason::ALL.len(),
+    )
+}
+
+pub struct Pins {
+    dir: PathBuf,
+}
+
+impl Pins {
+    // XDP and TC programs are attached with BPF links since 5.9 and 6.6, attachments with
+    // netlink cannot be pinned
+    pub fn new(instance: &str) -> anyhow::Result<Pins> {
+        let kernel = KernelVersion::current().map_err(|e| anyhow::Error::msg(e.to_string()))?;
+        if kernel < KernelVersion::new(6, 6, 0) {
+            return Err(anyhow::Error::msg("Pinning needs BPF links for XDP and TC, available since kernel 6.6"));
+        }
+        Ok(Pins {
+            dir: Path::new(BPFFS).join(instance),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Pins of a previous run are reused if their layout is the one of this build and the
+    // programs are still attached. Otherwise they are removed and the programs attached again.
+    pub fn restorable(&self) -> anyhow::Result<bool> {
+        if !self.dir.exists() {
+            return Ok(false);
+        }
+
+        match fs::read_to_string(self.dir.join("layout")) {
+            Ok(found) if found.trim() == layout() => (),
+            Ok(found) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Maps pinned in {} have the layout `{}` instead of `{}`, remove them to start over",
+                    self.dir.display(),
+                    found.trim(),
+                    layout()
+                )))
+            }
+            Err(_) => {
+                warn!("Removing incomplete pins in {}", self.dir.display());
+                self.remove()?;
+                return Ok(false);
+            }
+        }
+
+        let links = self.links();
+        let attached = links.iter().any(|program| program == "bier_egress")
+            && links
+                .iter()
+                .all(|program| PinnedLink::from_pin(self.dir.join("links").join(program)).is_ok());
+        if !attached {
+            warn!("Programs pinned in {} are no longer attached, attaching them again", self.dir.display());
+            self.remove()?;
+        }
+        Ok(attached)
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        fs::remove_dir_all(&self.dir).with_context(|| format!("Failed removing {}", self.dir.display()))
+    }
+
+    // Pin the maps and links of freshly attached programs, so they stay attached after the
+    // daemon exits
+    pub fn pin(&self, maps: Vec<(String, MapData)>, links: Vec<(String, FdLink)>) -> anyhow::Result<()> {
+        let maps_dir = self.dir.join("maps");
+        let links_dir = self.dir.join("links");
+        fs::create_dir_all(&maps_dir).with_context(|| format!("Failed creating {}", maps_dir.display()))?;
+        fs::create_dir_all(&links_dir)?;
+
+        for (name, map) in maps {
+            map.pin(maps_dir.join(&name))
+                .with_context(|| format!("Failed pinning map {name}"))?;
+        }
+        for (program, link) in links {
+            link.pin(links_dir.join(&program))
+                .with_context(|| format!("Failed pinning link of {program}"))?;
+        }
+        fs::write(self.dir.join("layout"), layout()).context("Failed writing the layout of the pins")?;
+        Ok(())
+    }
+
+    pub fn map(&self, name: &str) -> anyhow::Result<Map> {
+        let path = self.dir.join("maps").join(name);
+        let data = MapData::from_pin(&path)
+            .with_context(|| format!("Failed opening pinned map {}", path.display()))?;
+
+        Ok(match data.info()?.map_type()? {
+            MapType::Array => Map::Array(data),
+            MapType::PerCpuArray => Map::PerCpuArray(data),
+            MapType::Hash => Map::HashMap(data),
+            MapType::PerCpuHash => Map::PerCpuHashMap(data),
+            MapType::RingBuf => Map::RingBuf(data),
+            _ => Map::Unsupported(data),
+        })
+    }
+
+    // Programs with a pinned link
+    pub fn links(&self) -> Vec<String> {
+        fs::read_dir(self.dir.join("links"))
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Second handle of a map taken from the programs, to pin it once they are attached
+pub fn map_handle(map: &Map) -> anyhow::Result<MapData> {
+    let data = match map {
+        Map::Array(data) | Map::PerCpuArray(data) | Map::HashMap(data) | Map::PerCpuHashMap(data) | Map::RingBuf(data) => data,
+        _ => return Err(anyhow::Error::msg("Unsupported map type for pinning")),
+    };
+    Ok(MapData::from_fd(data.fd().as_fd().try_clone_to_owned()?)?)
+}
+
+// Link of an attached program, to pin it once all programs are attached
+pub fn fd_link<L>(program: &str, link: L) -> anyhow::Result<FdLink>
+where
+    FdLink: TryFrom<L, Error = LinkError>,
+{
+    FdLink::try_from(link).with_context(|| format!("{program} is not attached with a BPF link"))
+}
@@ -0,0 +1,149 @@
+// Packet and bit rates of the TUI rows, computed from counter deltas between samples
+// and kept as history for the sparklines
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+// Samples are taken at most once per interval, short intervals of key presses would be noisy
+pub const INTERVAL: Duration = Duration::from_secs(1);
+// Samples of the history, at one per second the last two minutes
+pub const HISTORY: usize = 120;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rate {
+    pub pps: f64,
+    pub bps: f64,
+}
+
+#[derive(Default)]
+pub struct Rates {
+    sampled: Option<Instant>,
+    // Packets and bytes of the last sample
+    last: HashMap<String, (u64, u64)>,
+    rates: HashMap<String, Rate>,
+    // Packets per second, oldest first
+    history: HashMap<String, VecDeque<u64>>,
+}
+
+impl Rates {
+    // Counters are given by key with packets and bytes, keys that are gone are dropped
+    pub fn update(&mut self, now: Instant, counters: impl Iterator<Item = (String, u64, u64)>) {
+        let elapsed = match self.sampled {
+            Some(sampled) if now.duration_since(sampled) < INTERVAL => return,
+            Some(sampled) => Some(now.duration_since(sampled).as_secs_f64()),
+            None => None,
+        };
+        self.sampled = Some(now);
+
+        let mut last = HashMap::new();
+        let mut rates = HashMap::new();
+        for (key, packets, bytes) in counters {
+            // Counters that were reset count from zero
+            if let (Some(elapsed), Some((last_packets, last_bytes))) = (elapsed, self.last.get(&key)) {
+                let rate = Rate {
+                    pps: packets.saturating_sub(*last_packets) as f64 / elapsed,
+                    bps: bytes.saturating_sub(*last_bytes) as f64 * 8.0 / elapsed,
+                };
+                let history = self.history.entry(key.clone()).or_default();
+                if history.len() == HISTORY {
+                    history.pop_front();
+                }
+                history.push_back(rate.pps.round() as u64);
+                rates.insert(key.clone(), rate);
+            }
+            last.insert(key, (packets, bytes));
+        }
+        self.history.retain(|key, _| last.contains_key(key));
+        self.last = last;
+        self.rates = rates;
+    }
+
+    pub fn rate(&self, key: &str) -> Rate {
+        self.rates.get(key).copied().unwrap_or_default()
+    }
+
+    // The most recent samples that fit into `width`, oldest first
+    pub fn history(&self, key: &str, width: usize) -> Vec<u64> {
+        self.history
+            .get(key)
+            .map(|history| history.iter().skip(history.len().saturating_sub(width)).copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+// Rate with SI prefix, e.g. `1.2 M`
+pub fn format_rate(value: f64) -> String {
+    let mut value = value;
+    for prefix in ["", "k", "M", "G"] {
+        if value < 1000.0 {
+            return format!("{value:.1} {prefix}");
+        }
+        value /= 1000.0;
+    }
+    format!("{value:.1} T")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(key: &str, packets: u64, bytes: u64) -> std::iter::Once<(String, u64, u64)> {
+        std::iter::once((key.to_string(), packets, bytes))
+    }
+
+    #[test]
+    fn test_rates() {
+        let mut rates = Rates::default();
+        let start = Instant::now();
+
+        // The first sample has nothing to compare with
+        rates.update(start, sample("239.1.1.1", 100, 10_000));
+        assert_eq!(rates.rate("239.1.1.1").pps, 0.0);
+
+        // Samples within the interval are ignored
+        rates.update(start + INTERVAL / 2, sample("239.1.1.1", 1_000, 100_000));
+        assert_eq!(rates.rate("239.1.1.1").pps, 0.0);
+
+        rates.update(start + INTERVAL * 2, sample("239.1.1.1", 300, 20_000));
+        let rate = rates.rate("239.1.1.1");
+        assert_eq!(rate.pps, 100.0);
+        assert_eq!(rate.bps, 40_000.0);
+        assert_eq!(rates.history("239.1.1.1", 10), vec![100]);
+
+        // Reset counters count from zero
+        rates.update(start + INTERVAL * 3, sample("239.1.1.1", 50, 5_000));
+        assert_eq!(rates.rate("239.1.1.1").pps, 0.0);
+
+        // Keys that are gone are dropped with their history
+        rates.update(start + INTERVAL * 4, sample("239.1.1.2", 1, 1));
+        assert_eq!(rates.rate("239.1.1.1").pps, 0.0);
+        assert!(rates.history("239.1.1.1", 10).is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut rates = Rates::default();
+        let start = Instant::now();
+        for i in 0..=HISTORY as u64 + 10 {
+            rates.update(start + INTERVAL * i as u32, sample("239.1.1.1", i * i, 0));
+        }
+
+        // Only the newest samples are kept, oldest first
+        let history = rates.history("239.1.1.1", usize::MAX);
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!(rates.history("239.1.1.1", 3), vec![255, 257, 259]);
+    }
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(0.0), "0.0 ");
+        assert_eq!(format_rate(999.0), "999.0 ");
+        assert_eq!(format_rate(1_200.0), "1.2 k");
+        assert_eq!(format_rate(1_500_000.0), "1.5 M");
+        assert_eq!(format_rate(2_000_000_000.0), "2.0 G");
+        assert_eq!(format_rate(3e12), "3.0 T");
+        assert_eq!(format_rate(5e15), "5000.0 T");
+    }
+}
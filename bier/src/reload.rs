@@ -0,0 +1,64 @@
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use tokio::signal::unix::{signal, SignalKind};
+
+// Detects when the config has to be reloaded, on SIGHUP or when the file was modified.
+// A modification is only taken as loaded once the reload succeeded, failed reloads are retried.
+pub struct ConfigWatch {
+    file: String,
+    modified: Option<SystemTime>,
+    // Modification time seen by the last check, not reloaded yet
+    pending: Option<SystemTime>,
+    hangup: Arc<AtomicBool>,
+}
+
+fn modified(file: &str) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|meta| meta.modified()).ok()
+}
+
+impl ConfigWatch {
+    // Has to be created within the tokio runtime, which receives the signals
+    pub fn new(file: &str) -> anyhow::Result<ConfigWatch> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        let mut signals = signal(SignalKind::hangup())?;
+        let flag = hangup.clone();
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+
+        Ok(ConfigWatch {
+            file: file.to_string(),
+            modified: modified(file),
+            pending: None,
+            hangup,
+        })
+    }
+
+    // The file was written by the daemon itself and is not reloaded
+    pub fn written(&mut self) {
+        self.modified = modified(&self.file);
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let hangup = self.hangup.swap(false, Ordering::Relaxed);
+        self.pending = modified(&self.file);
+        let changed = self.pending.is_some() && self.pending != self.modified;
+        hangup || changed
+    }
+
+    // The reload after `changed` succeeded, the file is not reloaded until modified again
+    pub fn loaded(&mut self) {
+        if self.pending.is_some() {
+            self.modified = self.pending;
+        }
+    }
+}
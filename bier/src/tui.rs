@@ -11,7 +11,8 @@ use ratatui::{
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table},
+    text::Line,
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Sparkline, Table},
     Frame, Terminal,
 };
 use std::{
@@ -19,17 +20,42 @@ use std::{
     io::{self, Read, Stdout},
     net::IpAddr,
     str::FromStr,
-    time::Duration,
+    iter,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use crate::{config::BierMapping, mapping::Mappings};
+use bier_common::{reason::Reason, ttl::TtlExpiry};
+use log::{Level, LevelFilter};
+
+use crate::{
+    config::BierMapping,
+    editor::Editor,
+    logs::LogBuffer,
+    mapping::Mappings,
+    rates::{format_rate, Rates},
+    reload::ConfigWatch,
+};
+
+// Key of the aggregate rate of all groups
+const ALL_GROUPS: &str = "all groups";
 
 pub struct RowData {
     pub ipmc_group: String,
     pub packet_count: u64,
+    pub byte_count: u64,
+    pub bits: usize,
     pub bitstring: String,
 }
 
+// Receive side counter of decapsulated packets
+pub struct RxRowData {
+    pub kind: &'static str,
+    pub key: String,
+    pub packet_count: u64,
+    pub byte_count: u64,
+}
+
 fn truncate_bitstring(bitstring: &str, max_len: usize) -> String {
     if bitstring.len() <= max_len {
         bitstring.to_string()
@@ -38,23 +64,89 @@ fn truncate_bitstring(bitstring: &str, max_len: usize) -> String {
     }
 }
 
-pub fn run_tui(mappings: Mappings) -> Result<(), Box<dyn std::error::Error>> {
+// `ingress` is the attach mode of the decapsulation shown in the title
+pub fn run_tui(
+    shared: Arc<Mutex<Mappings>>,
+    ingress: &str,
+    mut watch: ConfigWatch,
+    logs: LogBuffer,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Terminal setup
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    logs.set_stderr(false);
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // App state
     let mut selected_index = 0;
-    let mut show_popup = false;
-    let mut popup_text: String = String::new();
+    let mut editor: Option<Editor> = None;
+    // Group to delete once `d` is pressed again
+    let mut pending_delete: Option<IpAddr> = None;
+    let mut last_expiry: Option<TtlExpiry> = None;
+    let mut last_reload: Option<String> = None;
+    let mut last_edit: Option<String> = None;
+    let mut rates = Rates::default();
+    let mut show_logs = false;
+    // Lines scrolled up from the newest record
+    let mut log_scroll: usize = 0;
 
     // Main loop
     loop {
+        // Control socket is blocked until the frame is drawn
+        let mut mappings = shared.lock().unwrap();
+
+        if watch.changed() {
+            last_reload = Some(match mappings.reload() {
+                Ok(diff) => {
+                    watch.loaded();
+                    format!("reloaded: {diff}")
+                }
+                Err(e) => format!("reload failed: {e:#}"),
+            });
+        }
+
         // Data is updated with every loop iteration
         let data: Vec<RowData> = mappings.get_data();
+        // Reloads may remove rows
+        selected_index = selected_index.min(data.len().saturating_sub(1));
+        // Missing counters are shown as u64::MAX and have no rate
+        let counters = data.iter().filter(|row| row.packet_count != u64::MAX);
+        let (packets, bytes) = counters
+            .clone()
+            .filter(|row| IpAddr::from_str(&row.ipmc_group).is_ok())
+            .fold((0u64, 0u64), |(packets, bytes), row| {
+                (packets.wrapping_add(row.packet_count), bytes.wrapping_add(row.byte_count))
+            });
+        rates.update(
+            Instant::now(),
+            counters
+                .map(|row| (row.ipmc_group.clone(), row.packet_count, row.byte_count))
+                .chain(iter::once((ALL_GROUPS.to_string(), packets, bytes))),
+        );
+        let rx_data: Vec<RxRowData> = mappings.get_rx_data();
+        let reasons: Vec<(Reason, u64)> = mappings.get_reasons();
+        if let Some(expiry) = mappings.poll_ttl_events().pop() {
+            last_expiry = Some(expiry);
+        }
+        let mut title = format!("BIER(-TE) Mapper | {ingress}");
+        if let Some(expiry) = last_expiry {
+            title += &format!(
+                " | last TTL expiry: BIFT-id {} from BFIR-id {}",
+                expiry.bift_id, expiry.bfir_id
+            );
+        }
+        if let Some(reload) = &last_reload {
+            title += &format!(" | {reload}");
+        }
+        if let Some(group) = pending_delete {
+            title += &format!(" | (d) again to delete {group}");
+        } else if let Some(edit) = &last_edit {
+            title += &format!(" | {edit}");
+        }
+        title += " | (enter) edit (a) add (d) delete (w) write config (l) logs (q) quit";
+        let log_lines = if show_logs { logs.lines() } else { Vec::new() };
 
         terminal.draw(|f| {
             let size = f.area();
@@ -62,56 +154,133 @@ pub fn run_tui(mappings: Mappings) -> Result<(), Box<dyn std::error::Error>> {
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(2)
-                .constraints([Constraint::Min(0)].as_ref())
+                .constraints(if show_logs {
+                    vec![
+                        Constraint::Min(8),
+                        Constraint::Length(7),
+                        Constraint::Percentage(25),
+                        Constraint::Percentage(25),
+                    ]
+                } else {
+                    vec![Constraint::Min(8), Constraint::Length(7), Constraint::Percentage(35)]
+                })
                 .split(size);
 
-            draw_table(f, chunks[0], &data, selected_index);
-
-            if show_popup {
-                let popup_area = centered_rect(60, 80, size);
-                
-                // Only capture text on first call
-                if popup_text.is_empty() {
-                    popup_text = match IpAddr::from_str(&data[selected_index].ipmc_group) {
-                    Ok(addr) => {
-                        let headers = mappings.get_header_fields(addr);
-                        headers.join("\n")
-                    }
-                    Err(err) => err.to_string(),
-                };
-                }
+            draw_table(f, chunks[0], &data, &rates, selected_index, &title);
+            let history = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(chunks[1]);
+            if let Some(row) = data.get(selected_index) {
+                draw_sparkline(f, history[0], &rates, &row.ipmc_group);
+            }
+            draw_sparkline(f, history[1], &rates, ALL_GROUPS);
 
-                
-                draw_popup(f, popup_area, &popup_text);
-            } else {
-                // Reload content on next popup call
-                popup_text.clear();
+            let bottom = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+                .split(chunks[2]);
+
+            draw_rx_table(f, bottom[0], &rx_data);
+            draw_reason_table(f, bottom[1], &reasons);
+            if show_logs {
+                draw_logs(f, chunks[3], &log_lines, logs.level(), log_scroll);
+            }
+
+            if let Some(editor) = &editor {
+                draw_editor(f, centered_rect(60, 80, size), editor);
             }
         })?;
+        drop(mappings);
 
         // Event handling
+        let selected_group = data
+            .get(selected_index)
+            .and_then(|row| IpAddr::from_str(&row.ipmc_group).ok());
         if event::poll(Duration::from_millis(200))? {
             match event::read()? {
-                Event::Key(key) => match key.code {
-                    KeyCode::Char('q') => break,
-                    KeyCode::Down => {
-                        if selected_index < data.len() - 1 {
-                            selected_index += 1;
-                        }
+                Event::Key(key) if editor.is_some() => {
+                    let edit = editor.as_mut().unwrap();
+                    let typing = edit.input.is_some();
+                    match key.code {
+                        KeyCode::Esc if edit.escape() => editor = None,
+                        KeyCode::Enter => edit.enter(),
+                        KeyCode::Up => edit.up(),
+                        KeyCode::Down => edit.down(),
+                        KeyCode::Left => edit.left(),
+                        KeyCode::Right => edit.right(),
+                        KeyCode::Backspace => edit.backspace(),
+                        KeyCode::Char(c) if typing => edit.type_char(c),
+                        KeyCode::Char(' ') => edit.toggle_bit(),
+                        KeyCode::Char('s') => match edit.apply(&mut shared.lock().unwrap()) {
+                            Ok(result) => {
+                                last_edit = Some(result);
+                                editor = None;
+                            }
+                            Err(e) => edit.error = Some(format!("{e:#}")),
+                        },
+                        _ => {}
                     }
-                    KeyCode::Up => {
-                        if selected_index > 0 {
-                            selected_index -= 1;
+                }
+                Event::Key(key) => {
+                    let delete = pending_delete.take();
+                    match key.code {
+                        KeyCode::Char('q') => break,
+                        KeyCode::Down => {
+                            if selected_index < data.len().saturating_sub(1) {
+                                selected_index += 1;
+                            }
                         }
+                        KeyCode::Up => {
+                            if selected_index > 0 {
+                                selected_index -= 1;
+                            }
+                        }
+                        KeyCode::Enter => {
+                            if let Some(group) = selected_group {
+                                let hdr = shared.lock().unwrap().entries().get(&group).cloned();
+                                editor = hdr.map(|hdr| Editor::edit(group, hdr));
+                            }
+                        }
+                        KeyCode::Char('a') => {
+                            let template = selected_group
+                                .and_then(|group| shared.lock().unwrap().entries().get(&group).cloned());
+                            editor = Some(Editor::add(template));
+                        }
+                        KeyCode::Char('d') => match (delete, selected_group) {
+                            (Some(group), Some(selected)) if group == selected => {
+                                last_edit = Some(match shared.lock().unwrap().remove_mapping(group) {
+                                    Ok(()) => format!("deleted {group}"),
+                                    Err(e) => format!("delete failed: {e:#}"),
+                                });
+                            }
+                            (_, selected) => pending_delete = selected,
+                        },
+                        KeyCode::Char('l') => {
+                            show_logs = !show_logs;
+                            log_scroll = 0;
+                        }
+                        KeyCode::Char('f') if show_logs => {
+                            logs.next_level();
+                        }
+                        KeyCode::PageUp if show_logs => {
+                            log_scroll = (log_scroll + 10).min(log_lines.len().saturating_sub(1));
+                        }
+                        KeyCode::PageDown if show_logs => {
+                            log_scroll = log_scroll.saturating_sub(10);
+                        }
+                        KeyCode::Char('w') => {
+                            last_edit = Some(match shared.lock().unwrap().save() {
+                                Ok(()) => {
+                                    watch.written();
+                                    "config written".to_string()
+                                }
+                                Err(e) => format!("writing config failed: {e:#}"),
+                            });
+                        }
+                        _ => {}
                     }
-                    KeyCode::Enter => {
-                        show_popup = true;
-                    }
-                    KeyCode::Esc => {
-                        show_popup = false;
-                    }
-                    _ => {}
-                },
+                }
                 _ => {}
             }
         }
@@ -128,7 +297,7 @@ pub fn run_tui(mappings: Mappings) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn draw_table(f: &mut Frame, area: Rect, data: &[RowData], selected: usize) {
+fn draw_table(f: &mut Frame, area: Rect, data: &[RowData], rates: &Rates, selected: usize, title: &str) {
     let rows: Vec<Row> = data
         .iter()
         .enumerate()
@@ -138,9 +307,14 @@ fn draw_table(f: &mut Frame, area: Rect, data: &[RowData], selected: usize) {
             } else {
                 Style::default()
             };
+            let rate = rates.rate(&row.ipmc_group);
             Row::new(vec![
                 Cell::from(row.ipmc_group.clone()),
                 Cell::from(row.packet_count.to_string()),
+                Cell::from(row.byte_count.to_string()),
+                Cell::from(format_rate(rate.pps)),
+                Cell::from(format_rate(rate.bps)),
+                Cell::from(row.bits.to_string()),
                 Cell::from(truncate_bitstring(&row.bitstring, 256)),
             ])
             .style(style)
@@ -148,36 +322,153 @@ fn draw_table(f: &mut Frame, area: Rect, data: &[RowData], selected: usize) {
         .collect();
 
     let widths = &[
-        Constraint::Percentage(30), // IPMC-Group
+        Constraint::Percentage(25), // IPMC-Group
         Constraint::Length(15),     // Packet Count
-        Constraint::Percentage(55), // Bitstring
+        Constraint::Length(15),     // Byte Count
+        Constraint::Length(10),     // Packets per second
+        Constraint::Length(10),     // Bits per second
+        Constraint::Length(6),      // BSL in bits
+        Constraint::Percentage(35), // Bitstring
     ];
     let table = Table::new(rows, widths)
         .header(
-            Row::new(vec!["IPMC-Group", "Packet Count", "Bitstring"])
+            Row::new(vec!["IPMC-Group", "Packet Count", "Byte Count", "Packets/s", "Bits/s", "BSL", "Bitstring"])
                 .style(Style::default().fg(Color::Yellow)),
         )
         .block(
             Block::default()
-                .title("BIER(-TE) Mapper | (q) to quit")
+                .title(title)
                 .borders(Borders::ALL),
         )
         .widths(&[
-            Constraint::Percentage(30),
+            Constraint::Percentage(25),
+            Constraint::Length(15),
             Constraint::Length(15),
-            Constraint::Percentage(55),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Percentage(35),
         ]);
 
     f.render_widget(table, area);
 }
 
-fn draw_popup(f: &mut Frame, area: Rect, text: &str) {
+// Packets per second of the recent samples
+fn draw_sparkline(f: &mut Frame, area: Rect, rates: &Rates, key: &str) {
+    let rate = rates.rate(key);
+    let history = rates.history(key, area.width.saturating_sub(2) as usize);
+    let title = format!("{key} | {}pps | {}bit/s", format_rate(rate.pps), format_rate(rate.bps));
+
+    let sparkline = Sparkline::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .data(&history)
+        .style(Style::default().fg(Color::Green));
+
+    f.render_widget(sparkline, area);
+}
+
+// Newest records at the bottom, `scroll` lines back in history
+fn draw_logs(f: &mut Frame, area: Rect, lines: &[(Level, String)], level: LevelFilter, scroll: usize) {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = lines.len().saturating_sub(scroll);
+    let start = end.saturating_sub(height);
+    let lines: Vec<Line> = lines[start..end]
+        .iter()
+        .map(|(level, line)| {
+            let style = match level {
+                Level::Error => Style::default().fg(Color::Red),
+                Level::Warn => Style::default().fg(Color::Yellow),
+                _ => Style::default(),
+            };
+            Line::styled(line.clone(), style)
+        })
+        .collect();
+
+    let title = format!("Log | level {level} | (f) level (PgUp/PgDn) scroll");
+    let paragraph = Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL));
+
+    f.render_widget(paragraph, area);
+}
+
+fn draw_rx_table(f: &mut Frame, area: Rect, data: &[RxRowData]) {
+    let rows: Vec<Row> = data
+        .iter()
+        .map(|row| {
+            Row::new(vec![
+                Cell::from(row.kind),
+                Cell::from(row.key.clone()),
+                Cell::from(row.packet_count.to_string()),
+                Cell::from(row.byte_count.to_string()),
+            ])
+        })
+        .collect();
+
+    let widths = [
+        Constraint::Length(12),     // Counter type
+        Constraint::Percentage(50), // Group, BFIR-id or BIFT-id
+        Constraint::Length(15),     // Packet Count
+        Constraint::Length(15),     // Byte Count
+    ];
+    let table = Table::new(rows, widths)
+        .header(
+            Row::new(vec!["Type", "Key", "Packet Count", "Byte Count"])
+                .style(Style::default().fg(Color::Yellow)),
+        )
+        .block(Block::default().title("Received (decapsulated)").borders(Borders::ALL));
+
+    f.render_widget(table, area);
+}
+
+fn draw_reason_table(f: &mut Frame, area: Rect, reasons: &[(Reason, u64)]) {
+    let rows: Vec<Row> = reasons
+        .iter()
+        .map(|(reason, count)| {
+            let style = if reason.is_drop() && *count > 0 {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                Cell::from(reason.name()),
+                Cell::from(if reason.is_drop() { "drop" } else { "pass" }),
+                Cell::from(count.to_string()),
+            ])
+            .style(style)
+        })
+        .collect();
+
+    let widths = [
+        Constraint::Percentage(50), // Reason
+        Constraint::Length(6),      // Drop or pass
+        Constraint::Length(15),     // Packet Count
+    ];
+    let table = Table::new(rows, widths)
+        .header(
+            Row::new(vec!["Reason", "Action", "Packet Count"])
+                .style(Style::default().fg(Color::Yellow)),
+        )
+        .block(Block::default().title("Dropped / passed unmodified").borders(Borders::ALL));
+
+    f.render_widget(table, area);
+}
+
+fn draw_editor(f: &mut Frame, area: Rect, editor: &Editor) {
+    let title = if editor.input.is_some() {
+        "BIER Header | (enter) accept (esc) cancel"
+    } else {
+        "BIER Header | (enter) edit (space) toggle bit (s) apply (esc) close"
+    };
     let block = Block::default()
-        .title("BIER Header | (esc) to close!")
+        .title(title)
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::Black).fg(Color::White));
 
-    let paragraph = Paragraph::new(text).block(block).alignment(Alignment::Left);
+    let mut lines: Vec<Line> = editor.lines().into_iter().map(Line::from).collect();
+    if let Some(error) = &editor.error {
+        lines.push(Line::from(""));
+        lines.push(Line::styled(error.clone(), Style::default().fg(Color::Red)));
+    }
+    let paragraph = Paragraph::new(lines).block(block).alignment(Alignment::Left);
 
     f.render_widget(Clear, area); // Clear underneath
     f.render_widget(paragraph, area);
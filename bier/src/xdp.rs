@@ -0,0 +1,92 @@
+// Replacement of an XDP program that is already attached to an interface, as
+// `ip link set dev <iface> xdp obj ...` does it. BPF links (kernel >= 5.9) reject
+// XDP_FLAGS_REPLACE, so the program is set with netlink and the fd of the attached one as
+// IFLA_XDP_EXPECTED_FD. The kernel refuses the replacement if that program changed meanwhile.
+
+use std::{
+    ffi::CString,
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd},
+};
+
+use aya::programs::{loaded_programs, ProgramFd, XdpFlags};
+
+use crate::netlink::{attr, attrs, request};
+
+const IFLA_XDP_FD: u16 = 1;
+const IFLA_XDP_FLAGS: u16 = 3;
+const IFLA_XDP_PROG_ID: u16 = 4;
+const IFLA_XDP_EXPECTED_FD: u16 = 8;
+
+const IFINFOMSG_LEN: usize = 16;
+
+// ifinfomsg of the interface followed by `attrs`
+fn link_body(if_index: u32, attrs: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(IFINFOMSG_LEN + attrs.len());
+    // ifi_family, padding, ifi_type, ifi_index, ifi_flags, ifi_change
+    body.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    body.extend_from_slice(&(if_index as i32).to_ne_bytes());
+    body.extend_from_slice(&[0; 8]);
+    body.extend_from_slice(attrs);
+    body
+}
+
+pub fn if_index(iface: &str) -> io::Result<u32> {
+    let name = CString::new(iface).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+// Id of the XDP program attached to the interface, if any
+pub fn attached_id(if_index: u32) -> io::Result<Option<u32>> {
+    let payload = request(libc::RTM_GETLINK, 0, &link_body(if_index, &[]))?;
+    let link_attrs = payload.get(IFINFOMSG_LEN..).unwrap_or_default();
+    let id = attrs(link_attrs)
+        .into_iter()
+        .filter(|(kind, _)| *kind == libc::IFLA_XDP)
+        .flat_map(|(_, xdp)| attrs(xdp))
+        .find(|(kind, payload)| *kind == IFLA_XDP_PROG_ID && payload.len() == 4)
+        .map(|(_, payload)| u32::from_ne_bytes(payload.try_into().unwrap()));
+    Ok(id.filter(|id| *id != 0))
+}
+
+// Fd of the XDP program attached to the interface, if any
+pub fn attached(if_index: u32) -> anyhow::Result<Option<ProgramFd>> {
+    let Some(id) = attached_id(if_index)? else {
+        return Ok(None);
+    };
+    for info in loaded_programs() {
+        let info = info?;
+        if info.id() == id {
+            return Ok(Some(info.fd()?));
+        }
+    }
+    Ok(None)
+}
+
+// Set `prog` (None detaches) in place of `old`, the mode is taken from `flags`
+pub fn replace(if_index: u32, prog: Option<BorrowedFd>, old: BorrowedFd, flags: XdpFlags) -> io::Result<()> {
+    let fd = prog.map(|fd| fd.as_raw_fd()).unwrap_or(-1);
+    let flags = (flags | XdpFlags::REPLACE).bits();
+
+    let mut xdp = attr(IFLA_XDP_FD, &fd.to_ne_bytes());
+    xdp.extend(attr(IFLA_XDP_FLAGS, &flags.to_ne_bytes()));
+    xdp.extend(attr(IFLA_XDP_EXPECTED_FD, &old.as_raw_fd().to_ne_bytes()));
+    let nested = attr(libc::IFLA_XDP | libc::NLA_F_NESTED as u16, &xdp);
+
+    request(libc::RTM_SETLINK, libc::NLM_F_ACK as u16, &link_body(if_index, &nested)).map(|_| ())
+}
+
+// The replaced program is attached with netlink and not detached when the fd is closed
+pub struct Replaced {
+    pub if_index: u32,
+    pub flags: XdpFlags,
+}
+
+impl Replaced {
+    pub fn detach(&self, prog: &ProgramFd) -> io::Result<()> {
+        replace(self.if_index, None, prog.as_fd(), self.flags)
+    }
+}
@@ -38,4 +38,15 @@ while True:
 
     # Send the packet on interface (adjust iface name)
     sendp(pkt, iface="lo", verbose=True)
-    
\ No newline at end of file
+
+    addr_6_choice = choice(addr6)
+
+    # Build packet: Ethernet / IPv6 / UDP
+    pkt6 = (
+        Ether(dst="33:33:00:00:00:01", src="02:42:ac:11:00:01", type=0x86DD) /
+        IPv6(dst=addr_6_choice, src="fd00::1") /
+        UDP(sport=12345, dport=54321) /
+        Raw(load="Hello via BIER")
+    )
+
+    sendp(pkt6, iface="lo", verbose=True)
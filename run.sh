@@ -1,3 +1,3 @@
 #!/bin/bash
-cargo run --release --config 'target."cfg(all())".runner="sudo -E"' -- --iface lo --config config.json
+cargo run --release --config 'target."cfg(all())".runner="sudo -E"' -- run --iface lo --config config.json
 
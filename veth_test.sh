@@ -0,0 +1,82 @@
+#!/bin/bash
+# Decapsulation at TC ingress over a veth pair: Ethernet BIER (0xAB37) from BFIR-id 1 and
+# MPLS BIER (0x8847) from BFIR-id 2 are sent from a namespace to BFR-id 1 with `--ingress tc`,
+# BIER-TE from BFIR-id 3 to the local_decap adjacency of bit 1.
+# Needs root, scapy and a release build (`cargo build --release`).
+set -euo pipefail
+
+BIER=${BIER:-target/release/bier}
+DIR=$(mktemp -d)
+SOCK=$DIR/bier.sock
+
+cleanup() {
+    kill "$PID" 2>/dev/null || true
+    wait "$PID" 2>/dev/null || true
+    ip link del veth-bier 2>/dev/null || true
+    ip netns del bier-peer 2>/dev/null || true
+    rm -rf "$DIR"
+}
+PID=
+trap cleanup EXIT
+
+ip netns add bier-peer
+ip link add veth-bier type veth peer name veth-peer netns bier-peer
+ip addr add 192.0.2.1/24 dev veth-bier
+ip link set veth-bier up
+ip -n bier-peer addr add 192.0.2.2/24 dev veth-peer
+ip -n bier-peer link set veth-peer up
+
+cat > "$DIR/config.json" <<EOF
+{
+  "local": [1],
+  "entries": {},
+  "bift_ids": {
+    "100003": { "mpls": true },
+    "100004": { "mode": "te", "adjacencies": [{ "bit": 1, "type": "local_decap" }] }
+  }
+}
+EOF
+
+"$BIER" --socket "$SOCK" run --iface veth-bier --config "$DIR/config.json" --ingress tc --headless --stats /dev/null &
+PID=$!
+for _ in $(seq 50); do
+    [ -S "$SOCK" ] && break
+    sleep 0.1
+done
+
+MAC=$(cat /sys/class/net/veth-bier/address)
+ip netns exec bier-peer python3 - "$MAC" <<'EOF'
+import sys
+from scapy.all import Ether, IP, UDP, Raw, sendp
+from scapy.fields import BitField, ByteField, ShortField
+from scapy.packet import Packet
+
+class BIER(Packet):
+    name = "BIER"
+    fields_desc = [
+        BitField("bift_id", 100003, 20), BitField("tc", 0, 3), BitField("s", 0, 1), ByteField("ttl", 64),
+        BitField("nibble", 0, 4), BitField("version", 0, 4), BitField("bsl", 1, 4), BitField("entropy", 0, 20),
+        BitField("oam", 0, 2), BitField("rsv", 0, 2), BitField("dscp", 0, 6), BitField("proto", 4, 6),
+        ShortField("bfir_id", 0),
+        # BFR-id 1 is the rightmost bit
+        BitField("bitstring", 1, 64),
+    ]
+
+inner = IP(src="192.0.2.2", dst="192.0.2.1") / UDP(sport=5000, dport=5000) / Raw(load="BIER")
+eth = Ether(dst=sys.argv[1], type=0xAB37) / BIER(bfir_id=1) / inner
+mpls = Ether(dst=sys.argv[1], type=0x8847) / BIER(bfir_id=2, s=1, nibble=5) / inner
+te = Ether(dst=sys.argv[1], type=0xAB37) / BIER(bift_id=100004, bfir_id=3) / inner
+sendp([eth, mpls, te], iface="veth-peer", verbose=False)
+EOF
+sleep 1
+
+"$BIER" --socket "$SOCK" --json show counters | python3 -c '
+import json, sys
+counters = json.load(sys.stdin)
+rx = {row["key"]: row["packets"] for row in counters["rx"]}
+failed = {row["reason"]: row["packets"] for row in counters["reasons"] if row["packets"]}
+ok = rx.get("BFIR-id 1") == 1 and rx.get("BFIR-id 2") == 1 and rx.get("BFIR-id 3") == 1 and not failed
+print("decapsulated:", rx, "reasons:", failed)
+sys.exit(0 if ok else 1)
+'
+echo "Ethernet BIER, MPLS BIER and BIER-TE decapsulated at TC ingress"